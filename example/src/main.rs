@@ -30,6 +30,9 @@ parser!{
     obj: JsonValue = whitespace >> "{" >>
         {key_value(",")} -> (|x| JsonValue::Object(x.into_iter().collect::<HashMap<String, JsonValue>>()))
         << "}"
+
+    #[entry]
+    json: JsonValue = value
 }
 
 fn main() {
@@ -43,5 +46,5 @@ fn main() {
   "Related companies" : [ "HPQ", "IBM", "YHOO", "DELL", "GOOG" ]
 }
 "#;
-    println!("{:?}", obj().run(input));
+    println!("{:?}", json().run(input));
 }
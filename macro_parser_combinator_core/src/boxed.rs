@@ -0,0 +1,179 @@
+use std::cell::RefCell;
+use std::ops::{Mul, Shr, Shl, BitOr};
+use std::rc::Rc;
+use std::sync::Arc;
+use crate::location::Location;
+use crate::parser::Parser;
+use crate::state::Checkpoint;
+
+/// a parser behind a pointer, all parsers with the same output have the same type.
+/// the combinators and operators of `Parser` work on it and return it boxed again,
+/// `parser()` borrows it as a `Parser` to combine it with others
+macro_rules! boxed_parser {
+    ($name: ident, $ptr: ident, [$($bound: tt)*]) => {
+        pub struct $name<'a, O>($ptr<dyn Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) $($bound)* + 'a>);
+
+        impl<'a, O> Clone for $name<'a, O> {
+            fn clone(&self) -> Self {
+                Self(self.0.clone())
+            }
+        }
+
+        impl<'a, O: 'a> $name<'a, O> {
+            pub fn new<F>(f: F) -> Self
+            where
+                F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) $($bound)* + 'a
+            {
+                Self($ptr::new(f))
+            }
+            pub fn parser(&self) -> Parser<&(dyn Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) $($bound)* + 'a), &'a str, O> {
+                Parser::new(&*self.0)
+            }
+            pub fn run_with_out(&self, input: &'a str, loc: Location) -> (Result<O, (String, Location)>, &'a str, Location) {
+                self.parser().run_with_out(input, loc)
+            }
+            pub fn run(&self, input: &'a str) -> Result<O, (String, Location)> {
+                self.parser().run(input)
+            }
+            pub fn run_complete(&self, input: &'a str) -> Result<O, (String, Location)> {
+                self.parser().run_complete(input)
+            }
+            pub fn to_try(self) -> $name<'a, Option<O>> {
+                $name::new(move |input, loc| self.parser().to_try().0(input, loc))
+            }
+            pub fn many(self) -> $name<'a, Vec<O>> {
+                $name::new(move |input, loc| self.parser().many().0(input, loc))
+            }
+            pub fn many_sep<Fs>(self, sep: Fs) -> $name<'a, Vec<O>>
+            where
+                Fs: Fn(&'a str, Location) -> (Option<&'a str>, Location) + Copy $($bound)* + 'a
+            {
+                $name::new(move |input, loc| self.parser().many_sep(sep).0(input, loc))
+            }
+            pub fn map<M, X: 'a>(self, m: M) -> $name<'a, X>
+            where
+                M: Fn(O) -> X + Copy $($bound)* + 'a
+            {
+                $name::new(move |input, loc| self.parser().map(m).0(input, loc))
+            }
+            pub fn and_then<M, X: 'a>(self, m: M) -> $name<'a, X>
+            where
+                M: Fn(O) -> Result<X, (String, Location)> + Copy $($bound)* + 'a
+            {
+                $name::new(move |input, loc| self.parser().and_then(m).0(input, loc))
+            }
+            pub fn label(self, name: &'static str) -> Self {
+                $name::new(move |input, loc| self.parser().label(name).0(input, loc))
+            }
+            pub fn context(self, name: &'static str) -> Self {
+                $name::new(move |input, loc| self.parser().context(name).0(input, loc))
+            }
+            pub fn trace(self, name: &'static str) -> Self {
+                $name::new(move |input, loc| self.parser().trace(name).0(input, loc))
+            }
+            pub fn recover<Fs>(self, sync: Fs) -> $name<'a, Result<O, (String, Location)>>
+            where
+                Fs: Fn(&'a str, Location) -> (Option<&'a str>, Location) + Copy $($bound)* + 'a
+            {
+                $name::new(move |input, loc| self.parser().recover(sync).0(input, loc))
+            }
+        }
+
+        boxed_parser!(@op $name, [$($bound)*], Mul, mul, <O1, O2>, O1, O2, (O1, O2));
+        boxed_parser!(@op $name, [$($bound)*], Shr, shr, <O1, O2>, O1, O2, O2);
+        boxed_parser!(@op $name, [$($bound)*], Shl, shl, <O1, O2>, O1, O2, O1);
+        boxed_parser!(@op $name, [$($bound)*], BitOr, bitor, <O>, O, O, O);
+    };
+    // `boxed op boxed`, `boxed op parser` and `parser op boxed` are boxed
+    (@op $name: ident, [$($bound: tt)*], $trait: ident, $method: ident, <$($o: ident),*>, $lo: ty, $ro: ty, $out: ty) => {
+        impl<'a, $($o: 'a),*> $trait<$name<'a, $ro>> for $name<'a, $lo> {
+            type Output = $name<'a, $out>;
+
+            fn $method(self, rhs: $name<'a, $ro>) -> Self::Output {
+                $name::new(move |input, loc| $trait::$method(self.parser(), rhs.parser()).0(input, loc))
+            }
+        }
+
+        impl<'a, F, $($o: 'a),*> $trait<Parser<F, &'a str, $ro>> for $name<'a, $lo>
+        where
+            F: Fn(&'a str, Location) -> (Result<$ro, (String, Location)>, &'a str, Location) + Copy $($bound)* + 'a
+        {
+            type Output = $name<'a, $out>;
+
+            fn $method(self, rhs: Parser<F, &'a str, $ro>) -> Self::Output {
+                // the closure keeps `rhs.0`, a `Parser` is not `Send` unless its output is
+                let rhs = rhs.0;
+                $name::new(move |input, loc| $trait::$method(self.parser(), Parser::new(rhs)).0(input, loc))
+            }
+        }
+
+        impl<'a, F, $($o: 'a),*> $trait<$name<'a, $ro>> for Parser<F, &'a str, $lo>
+        where
+            F: Fn(&'a str, Location) -> (Result<$lo, (String, Location)>, &'a str, Location) + Copy $($bound)* + 'a
+        {
+            type Output = $name<'a, $out>;
+
+            fn $method(self, rhs: $name<'a, $ro>) -> Self::Output {
+                let lhs = self.0;
+                $name::new(move |input, loc| $trait::$method(Parser::new(lhs), rhs.parser()).0(input, loc))
+            }
+        }
+    };
+}
+
+boxed_parser!(BoxedParser, Rc, []);
+boxed_parser!(SyncParser, Arc, [+ Send + Sync]);
+
+/// the state is a `RefCell`, so only `BoxedParser` can hold it
+impl<'a, O: 'a> BoxedParser<'a, O> {
+    pub fn map_state<S, M, X: 'a>(self, state: &'a RefCell<S>, m: M) -> BoxedParser<'a, X>
+    where
+        M: Fn(&mut S, O) -> X + Copy + 'a
+    {
+        BoxedParser::new(move |input, loc| self.parser().map_state(state, m).0(input, loc))
+    }
+    pub fn and_then_state<S, M, X: 'a>(self, state: &'a RefCell<S>, m: M) -> BoxedParser<'a, X>
+    where
+        M: Fn(&mut S, O) -> Result<X, (String, Location)> + Copy + 'a
+    {
+        BoxedParser::new(move |input, loc| self.parser().and_then_state(state, m).0(input, loc))
+    }
+    pub fn rollback<S: Checkpoint>(self, state: &'a RefCell<S>) -> Self {
+        BoxedParser::new(move |input, loc| self.parser().rollback(state).0(input, loc))
+    }
+}
+
+impl<'a, F, O: 'a> Parser<F, &'a str, O>
+where
+    F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy + 'a
+{
+    /// erase the type of the parser, so it can be stored or chosen at runtime
+    pub fn boxed(self) -> BoxedParser<'a, O> {
+        BoxedParser::new(self.0)
+    }
+    /// like `boxed`, and can be shared between threads
+    pub fn boxed_sync(self) -> SyncParser<'a, O>
+    where
+        F: Send + Sync
+    {
+        SyncParser::new(self.0)
+    }
+}
+
+#[test]
+fn test_boxed() {
+    use crate::char;
+    let digits: Vec<BoxedParser<char>> = vec![
+        char!('0').map(|_| '0').boxed(),
+        char!('1').map(|_| '1').boxed(),
+    ];
+    let bit = digits.into_iter().reduce(|a, b| a | b).unwrap();
+    let bits = (char!('b') >> bit.many()).map(|v| v.into_iter().collect::<String>());
+    assert_eq!(bits.run("b0110").unwrap(), "0110");
+    let pair = (bits.clone() << char!(',')) * bits;
+    assert_eq!(pair.run("b1,b0").unwrap(), ("1".to_string(), "0".to_string()));
+
+    let sync = char!('x').map(|_| 1).boxed_sync().many();
+    let n = std::thread::spawn(move || sync.run("xxx").unwrap().len()).join().unwrap();
+    assert_eq!(n, 3);
+}
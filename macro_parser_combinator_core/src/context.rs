@@ -0,0 +1,56 @@
+use std::cell::RefCell;
+use crate::location::Location;
+
+thread_local! {
+    static STACK: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+    /// rule stack of the innermost rule that failed, and where it failed
+    static TRACE: RefCell<Option<(Vec<&'static str>, Location)>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn enter(name: &'static str) {
+    STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        if stack.is_empty() {
+            TRACE.with(|trace| trace.take());
+        }
+        stack.push(name);
+    })
+}
+
+pub(crate) fn exit<O>(start: Location, ret: Result<O, (String, Location)>) -> Result<O, (String, Location)> {
+    STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let ret = match ret {
+            Ok(o) => Ok(o),
+            Err((msg, loc)) => {
+                TRACE.with(|trace| {
+                    let mut trace = trace.borrow_mut();
+                    let deeper = matches!(&*trace, Some((_, l)) if *l == loc);
+                    if loc == start || !deeper {
+                        *trace = Some((stack.clone(), loc));
+                    }
+                });
+                if stack.len() == 1 {
+                    match TRACE.with(|trace| trace.take()) {
+                        Some((rules, _)) => {
+                            let traced = format!("{} (in {})", msg, rules.join(" > "));
+                            crate::diagnostic::rename(&msg, &traced);
+                            Err((traced, loc))
+                        },
+                        None => Err((msg, loc)),
+                    }
+                } else {
+                    Err((msg, loc))
+                }
+            },
+        };
+        stack.pop();
+        ret
+    })
+}
+
+/// the innermost rule that is running
+#[cfg(debug_assertions)]
+pub(crate) fn current() -> Option<&'static str> {
+    STACK.with(|stack| stack.borrow().last().copied())
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+use crate::location::Location;
+use crate::parser::Parser;
+#[cfg(feature = "incremental")]
+use crate::incremental;
+
+/// from `start` up to `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+/// concrete syntax tree, made by the rules of `parser!` with `#![cst]`.
+/// it keeps the whitespace, printing it gives the matched input back
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cst {
+    /// a rule, the span is from its first token to its last
+    Node { rule: &'static str, span: Span, children: Vec<Cst> },
+    /// a token, a regex or a builtin like `int`, with the matched text.
+    /// the span is the text without the whitespace around it
+    Token { text: String, span: Span, leading: String, trailing: String },
+    /// whitespace that is not attached to a token, only in a rule without tokens
+    Trivia { text: String, span: Span },
+}
+
+impl Cst {
+    pub fn span(&self) -> Span {
+        match self {
+            Cst::Node { span, .. } | Cst::Token { span, .. } | Cst::Trivia { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for Cst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cst::Node { children, .. } => children.iter().try_for_each(|c| write!(f, "{}", c)),
+            Cst::Token { text, leading, trailing, .. } => write!(f, "{}{}{}", leading, text, trailing),
+            Cst::Trivia { text, .. } => write!(f, "{}", text),
+        }
+    }
+}
+
+fn first_token(c: &mut Cst) -> Option<&mut Cst> {
+    match c {
+        Cst::Token { .. } => Some(c),
+        Cst::Node { children, .. } => children.iter_mut().find_map(first_token),
+        Cst::Trivia { .. } => None,
+    }
+}
+
+fn last_token(c: &mut Cst) -> Option<&mut Cst> {
+    match c {
+        Cst::Token { .. } => Some(c),
+        Cst::Node { children, .. } => children.iter_mut().rev().find_map(last_token),
+        Cst::Trivia { .. } => None,
+    }
+}
+
+/// whitespace at the start of a rule is leading trivia of its first token,
+/// other whitespace is trailing trivia of the token before it
+fn attach(children: Vec<Cst>) -> Vec<Cst> {
+    if !children.iter().any(|c| matches!(c, Cst::Token { .. } | Cst::Node { .. })) {
+        return children;
+    }
+    let mut ret: Vec<Cst> = Vec::new();
+    let mut pending = String::new();
+    for mut c in children {
+        if let Cst::Trivia { text, span } = c {
+            let first = ret.is_empty();
+            match ret.iter_mut().rev().find_map(last_token) {
+                Some(Cst::Token { trailing, .. }) => trailing.push_str(&text),
+                _ if first => pending.push_str(&text),
+                _ => ret.push(Cst::Trivia { text, span }),
+            }
+            continue;
+        }
+        if !pending.is_empty() {
+            if let Some(Cst::Token { leading, .. }) = first_token(&mut c) {
+                leading.insert_str(0, &std::mem::take(&mut pending));
+            }
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+/// the children of `a * b`, `a >> b` and `a << b`
+pub fn concat((mut a, b): (Vec<Cst>, Vec<Cst>)) -> Vec<Cst> {
+    a.extend(b);
+    a
+}
+
+/// the children of `{a}`
+pub fn flatten(items: Vec<Vec<Cst>>) -> Vec<Cst> {
+    items.into_iter().flatten().collect()
+}
+
+/// the children of `{a(",")}`, the separators are leaves
+pub fn many_sep<'a, F>(item: Parser<F, &'a str, Vec<Cst>>, sep: &'static str) -> Parser<impl Fn(&'a str, Location) -> (Result<Vec<Cst>, (String, Location)>, &'a str, Location) + Copy, &'a str, Vec<Cst>>
+where
+    F: Fn(&'a str, Location) -> (Result<Vec<Cst>, (String, Location)>, &'a str, Location) + Copy
+{
+    let f = move |input: &'a str, loc: Location| {
+        let mut ret = Vec::new();
+        let mut text = input;
+        let mut loc_parse = loc;
+        while let (Ok(children), rest, rest_loc) = item.0(text, loc_parse) {
+            ret.extend(children);
+            match rest.strip_prefix(sep) {
+                Some(after) => {
+                    let end = rest_loc.update(sep).0;
+                    if end == loc_parse {
+                        // it would loop forever
+                        return (Err(("loop body matched empty input".to_string(), loc_parse)), input, loc);
+                    }
+                    ret.push(Cst::Token {
+                        text: sep.to_string(),
+                        span: Span { start: rest_loc, end },
+                        leading: String::new(),
+                        trailing: String::new(),
+                    });
+                    (text, loc_parse) = (after, end);
+                },
+                None => {
+                    (text, loc_parse) = (rest, rest_loc);
+                    break;
+                },
+            }
+        }
+        (Ok(ret), text, loc_parse)
+    };
+    Parser::new(f)
+}
+
+impl<'a, F, O> Parser<F, &'a str, O>
+where
+    F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy
+{
+    /// a leaf with the matched text instead of the value
+    pub fn leaf(self) -> Parser<impl Fn(&'a str, Location) -> (Result<Vec<Cst>, (String, Location)>, &'a str, Location) + Copy, &'a str, Vec<Cst>> {
+        let f = move |input: &'a str, loc: Location| {
+            let (ret, rest, rest_loc) = self.0(input, loc);
+            let leaf = ret.map(|_| vec![Cst::Token {
+                text: input[..input.len() - rest.len()].to_string(),
+                span: Span { start: loc, end: rest_loc },
+                leading: String::new(),
+                trailing: String::new(),
+            }]);
+            (leaf, rest, rest_loc)
+        };
+        Parser::new(f)
+    }
+    /// the matched text as trivia, nothing if it is empty
+    pub fn trivia(self) -> Parser<impl Fn(&'a str, Location) -> (Result<Vec<Cst>, (String, Location)>, &'a str, Location) + Copy, &'a str, Vec<Cst>> {
+        let f = move |input: &'a str, loc: Location| {
+            let (ret, rest, rest_loc) = self.0(input, loc);
+            let text = &input[..input.len() - rest.len()];
+            let trivia = ret.map(|_| match text {
+                "" => Vec::new(),
+                text => vec![Cst::Trivia { text: text.to_string(), span: Span { start: loc, end: rest_loc } }],
+            });
+            (trivia, rest, rest_loc)
+        };
+        Parser::new(f)
+    }
+}
+
+impl<'a, F> Parser<F, &'a str, Vec<Cst>>
+where
+    F: Fn(&'a str, Location) -> (Result<Vec<Cst>, (String, Location)>, &'a str, Location) + Copy
+{
+    /// the node of a rule, reused from the memo of `run_incremental` when it can be.
+    /// `grammar` is the module of the rule, rules of the same name in two grammars are not the same node
+    #[cfg_attr(not(feature = "incremental"), allow(unused_variables))]
+    pub fn node(self, grammar: &'static str, rule: &'static str) -> Parser<impl Fn(&'a str, Location) -> (Result<Cst, (String, Location)>, &'a str, Location) + Copy, &'a str, Cst> {
+        let f = move |input: &'a str, loc: Location| {
+            #[cfg(feature = "incremental")]
+            if let Some((node, rest, rest_loc)) = incremental::reuse(grammar, rule, input, loc) {
+                return (Ok(node), rest, rest_loc);
+            }
+            #[cfg(feature = "incremental")]
+            let ((ret, rest, rest_loc), read) = incremental::reading(|| self.0(input, loc));
+            #[cfg(not(feature = "incremental"))]
+            let (ret, rest, rest_loc) = self.0(input, loc);
+            let node = ret.map(|children| {
+                let mut children = attach(children);
+                let first = children.iter_mut().find_map(first_token).map(|c| c.span());
+                let last = children.iter_mut().rev().find_map(last_token).map(|c| c.span());
+                let span = match (first, last) {
+                    (Some(first), Some(last)) => Span { start: first.start, end: last.end },
+                    _ => Span { start: loc, end: loc },
+                };
+                Cst::Node { rule, span, children }
+            });
+            #[cfg(feature = "incremental")]
+            if let Ok(node) = &node {
+                incremental::record((grammar, rule), (input, loc), (rest, rest_loc), read, node);
+            }
+            (node, rest, rest_loc)
+        };
+        Parser::new(f)
+    }
+}
+
+#[test]
+fn test_many_sep_no_progress() {
+    let empty = crate::whitespace().map(|_| Vec::new());
+    assert_eq!(many_sep(empty, "").run("x"), Err(("loop body matched empty input".to_string(), Location::new())));
+}
@@ -0,0 +1,79 @@
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::location::Location;
+
+static LIMIT: AtomicUsize = AtomicUsize::new(128);
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// the limit was hit, every level fails until `run` returns this error
+    static EXCEEDED: RefCell<Option<(String, Location)>> = const { RefCell::new(None) };
+    /// a run is going on, so a run inside it is not the outermost one
+    static RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// how deep `tobox!` may nest, for all threads. every rule call of `parser!` is a level,
+/// so a bracket of the input that goes through two rules, like `value` and `array`, takes two.
+/// deeper input is an error instead of a stack overflow
+pub fn set_recursion_limit(limit: usize) {
+    LIMIT.store(limit, Ordering::Relaxed);
+}
+
+pub fn recursion_limit() -> usize {
+    LIMIT.load(Ordering::Relaxed)
+}
+
+/// one level of nesting, left when dropped
+pub struct Depth(());
+
+impl Depth {
+    pub fn enter(loc: Location) -> Result<Self, (String, Location)> {
+        if let Some(e) = EXCEEDED.with(|e| e.borrow().clone()) {
+            return Err(e);
+        }
+        DEPTH.with(|depth| {
+            let limit = recursion_limit();
+            if depth.get() >= limit {
+                let e = (format!("nested deeper than the recursion limit {}", limit), loc);
+                EXCEEDED.with(|exceeded| *exceeded.borrow_mut() = Some(e.clone()));
+                Err(e)
+            } else {
+                depth.set(depth.get() + 1);
+                Ok(Depth(()))
+            }
+        })
+    }
+}
+
+impl Drop for Depth {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// the error of the limit, other errors and alternatives tried after it are not the cause
+fn take_exceeded() -> Option<(String, Location)> {
+    EXCEEDED.with(|e| e.borrow_mut().take())
+}
+
+struct Running(bool);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.with(|r| r.set(self.0));
+    }
+}
+
+/// `run` as a parse of its own: the outermost one starts from depth 0 without the error
+/// of an earlier parse, and returns the error of the limit if it was hit
+pub(crate) fn run<T>(run: impl FnOnce() -> T) -> (T, Option<(String, Location)>) {
+    let outer = Running(RUNNING.with(|r| r.replace(true)));
+    if !outer.0 {
+        DEPTH.with(|depth| depth.set(0));
+        take_exceeded();
+        crate::diagnostic::clear();
+    }
+    let ret = run();
+    let exceeded = if outer.0 { None } else { take_exceeded() };
+    (ret, exceeded)
+}
@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use crate::location::Location;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+thread_local! {
+    /// the alternatives of the errors that `|` joined in the last parse, by message
+    static ALTERNATIVES: RefCell<HashMap<String, Vec<String>>> = RefCell::new(HashMap::new());
+}
+
+/// the error of `a | b` when both failed, `a or b`, with the alternatives kept for `Diagnostic`
+pub(crate) fn or(left: String, right: String) -> String {
+    ALTERNATIVES.with_borrow_mut(|alternatives| {
+        let message = format!("{} or {}", left, right);
+        let mut items = alternatives.remove(&left).unwrap_or_else(|| vec![left]);
+        items.extend(alternatives.remove(&right).unwrap_or_else(|| vec![right]));
+        alternatives.insert(message.clone(), items);
+        message
+    })
+}
+
+/// the message of an error became `to`, it has the alternatives of `from`
+#[cfg(feature = "context")]
+pub(crate) fn rename(from: &str, to: &str) {
+    ALTERNATIVES.with_borrow_mut(|alternatives| {
+        if let Some(items) = alternatives.remove(from) {
+            alternatives.insert(to.to_string(), items);
+        }
+    })
+}
+
+/// a parse starts, the alternatives of the last one are gone
+pub(crate) fn clear() {
+    ALTERNATIVES.with_borrow_mut(HashMap::clear);
+}
+
+/// annotated source snippet for a parse error. the alternatives of an error of `|` are
+/// kept until the next parse of the thread, make it before that
+///
+/// ```ignore
+/// if let Err(e) = obj().run(input) {
+///     eprintln!("{}", Diagnostic::new(input, &e).file("data.json").color(true));
+/// }
+/// ```
+pub struct Diagnostic<'a> {
+    source: &'a str,
+    file: Option<&'a str>,
+    message: &'a str,
+    /// the messages of the alternatives that failed, or the message
+    alternatives: Vec<String>,
+    loc: Location,
+    labels: Vec<&'a str>,
+    color: bool,
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(source: &'a str, err: &'a (String, Location)) -> Self {
+        Self {
+            source,
+            file: None,
+            message: &err.0,
+            alternatives: ALTERNATIVES.with_borrow(|a| a.get(&err.0).cloned()).unwrap_or_else(|| vec![err.0.clone()]),
+            loc: err.1,
+            labels: Vec::new(),
+            color: false,
+        }
+    }
+    pub fn file(mut self, file: &'a str) -> Self {
+        self.file = Some(file);
+        self
+    }
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.labels.push(label);
+        self
+    }
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+    /// the expected items of the error, one for each alternative of `|` that failed
+    pub fn expected(&self) -> Vec<&str> {
+        self.alternatives
+            .iter()
+            .map(|x| {
+                let x = x.strip_prefix("should be ")
+                    .or_else(|| x.strip_prefix("expected "))
+                    .unwrap_or(x);
+                x.split(" but get ").next().unwrap_or(x)
+            })
+            .collect()
+    }
+    fn paint(&self, style: &'static str) -> (&'static str, &'static str) {
+        if self.color {
+            (style, RESET)
+        } else {
+            ("", "")
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (red, red_end) = self.paint(RED);
+        let (blue, blue_end) = self.paint(BLUE);
+        let (bold, bold_end) = self.paint(BOLD);
+        let expected = self.expected();
+        let title = match expected.as_slice() {
+            [_] => self.message.to_string(),
+            items => format!("expected one of {}", items.join(", ")),
+        };
+        writeln!(f, "{red}error{red_end}{bold}: {title}{bold_end}")?;
+
+        let line_no = self.loc.line.to_string();
+        let pad = " ".repeat(line_no.len());
+        writeln!(f, "{pad}{blue}-->{blue_end} {}:{}:{}", self.file.unwrap_or("<input>"), self.loc.line, self.loc.col)?;
+        writeln!(f, "{pad} {blue}|{blue_end}")?;
+
+        let line = self.source
+            .split('\n')
+            .nth(self.loc.line.saturating_sub(1))
+            .unwrap_or("")
+            .trim_end_matches('\r');
+        // `Location` counts columns in bytes
+        let mut col = self.loc.col.saturating_sub(1).min(line.len());
+        while !line.is_char_boundary(col) {
+            col -= 1;
+        }
+        let indent: String = line[..col].chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = line[col..].chars()
+            .take_while(|c| !c.is_whitespace())
+            .count()
+            .max(1);
+        writeln!(f, "{blue}{line_no} |{blue_end} {line}")?;
+        write!(f, "{pad} {blue}|{blue_end} {indent}{red}{}{red_end}", "^".repeat(width))?;
+        for label in &self.labels {
+            write!(f, " {red}{label}{red_end}")?;
+        }
+        if expected.len() > 1 {
+            for item in expected {
+                write!(f, "\n{pad} {blue}={blue_end} expected: {item}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_diagnostic() {
+    let source = "{\n  \"a\" x\n}";
+    let err = ("should be token : but get x".to_string(), Location { line: 2, col: 7 });
+    let ret = Diagnostic::new(source, &err).file("a.json").label("here").to_string();
+    assert_eq!(ret, "error: should be token : but get x
+ --> a.json:2:7
+  |
+2 |   \"a\" x
+  |       ^ here");
+
+    let source = "\"é\" x";
+    let err = ("should be token : but get x".to_string(), Location::new().update("\"é\" ").0);
+    let ret = Diagnostic::new(source, &err).to_string();
+    assert!(ret.ends_with("1 | \"é\" x\n  |     ^"));
+}
+
+#[test]
+fn test_expected() {
+    use crate::{token, token_base, whitespace, Parser};
+    let input = "c";
+    let err = (token!("a") | token!("b") | token!("or")).run(input).unwrap_err();
+    let diagnostic = Diagnostic::new(input, &err);
+    assert_eq!(diagnostic.expected(), ["token a", "token b", "token or"]);
+    assert!(diagnostic.to_string().starts_with("error: expected one of token a, token b, token or"));
+    // a label with ` or ` in it is one item
+    let err = (token!("a").label("a or b") | token!("c").label("c")).run("x").unwrap_err();
+    assert_eq!(Diagnostic::new(input, &err).expected(), ["a or b", "c"]);
+    let err = token!("a").label("a or b").run("x").unwrap_err();
+    assert_eq!(Diagnostic::new(input, &err).expected(), ["a or b"]);
+    #[cfg(feature = "context")]
+    {
+        let err = (token!("a") | token!("b")).context("r").run(input).unwrap_err();
+        assert_eq!(Diagnostic::new(input, &err).expected(), ["token a", "token b"]);
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use crate::{lazy_static, regex, token_base, Regex};
+use crate::location::Location;
+use crate::Parser;
+use crate::depth::Depth;
+use crate::rule::{Defined, Rule};
+
+/// an item of a grammar built at runtime, the constructs of `parser!`
+pub enum Expr {
+    /// `"let"`, the whitespace after it is skipped
+    Token(String),
+    /// `r"[a-z]+"`, made by `Expr::regex`
+    Regex(Anchored),
+    /// a rule of the grammar, or a builtin: whitespace, eof, int, float, escaped_quoted
+    Rule(String),
+    /// `a * b`, the values of the items that are not `Skip`
+    Seq(Vec<Expr>),
+    /// the dropped side of `>>` and `<<`
+    Skip(Box<Expr>),
+    /// `a | b`
+    Or(Vec<Expr>),
+    /// `[a]`
+    Try(Box<Expr>),
+    /// `{a}`
+    Many(Box<Expr>),
+    /// `{a(",")}`
+    ManySep(Box<Expr>, String),
+    /// `a @ "label"`
+    Label(Box<Expr>, String),
+}
+
+/// a regex that only matches at the start of the input
+pub struct Anchored {
+    source: String,
+    regex: Regex,
+}
+
+impl Expr {
+    /// the regex only matches at the start of the input
+    pub fn regex(re: &str) -> Result<Self, regex::Error> {
+        Ok(Expr::Regex(Anchored { source: re.to_string(), regex: Regex::new(&format!("^(?:{})", re))? }))
+    }
+}
+
+/// the value of a grammar built at runtime
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tree {
+    /// the text matched by a token, a regex or a builtin
+    Text(String),
+    Seq(Vec<Tree>),
+    Try(Option<Box<Tree>>),
+    Many(Vec<Tree>),
+    /// the value of a rule, with its name
+    Rule(String, Box<Tree>),
+}
+
+type Output<'a> = (Result<Tree, (String, Location)>, &'a str, Location);
+
+/// rules built at runtime, from `Expr` or from the syntax of `parser!`.
+/// `map`, actions and recovery are Rust code, they are skipped when reading the syntax,
+/// calls, parameters, state, `=>` and `~` are not supported
+#[derive(Default)]
+pub struct Grammar {
+    rules: HashMap<String, Expr>,
+}
+
+impl Grammar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// add a rule, it replaces a rule with the same name
+    pub fn rule(mut self, name: &str, expr: Expr) -> Self {
+        self.rules.insert(name.to_string(), expr);
+        self
+    }
+    /// run a rule, like `Parser::run`
+    pub fn parse(&self, rule: &str, input: &str) -> Result<Tree, (String, Location)> {
+        match crate::depth::run(|| self.call(rule, input, Location::new()).0) {
+            (_, Some(e)) => Err(e),
+            (ret, None) => ret,
+        }
+    }
+    /// a rule is labeled with its name, unless it has a label
+    fn call<'a>(&self, name: &str, input: &'a str, loc: Location) -> Output<'a> {
+        let _depth = match Depth::enter(loc) {
+            Ok(d) => d,
+            Err(e) => return (Err(e), input, loc),
+        };
+        match self.rules.get(name) {
+            Some(e @ Expr::Label(..)) => wrap(name, self.run(e, input, loc)),
+            Some(e) => wrap(name, label(name, input, loc, self.run(e, input, loc))),
+            None => builtin(name, input, loc),
+        }
+    }
+    fn run<'a>(&self, e: &Expr, input: &'a str, loc: Location) -> Output<'a> {
+        match e {
+            Expr::Token(t) => match input.strip_prefix(t.as_str()) {
+                Some(rest) => {
+                    let (_, rest, ret_loc) = crate::whitespace().0(rest, loc.update(t).0);
+                    (Ok(Tree::Text(t.clone())), rest, ret_loc)
+                },
+                None => (
+                    Err((format!("should be token {} but get {}", t, input.get(0..t.len()).unwrap_or("")), loc)),
+                    input,
+                    loc
+                ),
+            },
+            Expr::Regex(re) => match re.regex.find(input) {
+                Some(m) => (Ok(Tree::Text(m.as_str().to_string())), &input[m.end()..], loc.update(m.as_str()).0),
+                None => (Err((format!("should be regex {}", re.source), loc)), input, loc),
+            },
+            Expr::Rule(name) => self.call(name, input, loc),
+            Expr::Seq(items) => {
+                let mut ret = Vec::new();
+                let mut text = input;
+                let mut loc_parse = loc;
+                for item in items {
+                    let (tree, rest, rest_loc) = self.run(item, text, loc_parse);
+                    match tree {
+                        Ok(t) => {
+                            if !matches!(item, Expr::Skip(_)) {
+                                ret.push(t);
+                            }
+                            text = rest;
+                            loc_parse = rest_loc;
+                        },
+                        Err(e) => return (Err(e), input, loc),
+                    }
+                }
+                let tree = if ret.len() == 1 {
+                    ret.remove(0)
+                } else {
+                    Tree::Seq(ret)
+                };
+                (Ok(tree), text, loc_parse)
+            },
+            Expr::Skip(e) => self.run(e, input, loc),
+            Expr::Or(alternatives) => {
+                let mut err: Option<(String, Location)> = None;
+                for a in alternatives {
+                    match self.run(a, input, loc) {
+                        (Ok(t), rest, rest_loc) => return (Ok(t), rest, rest_loc),
+                        (Err(e), _, _) => {
+                            err = Some(match err {
+                                Some(prev) => (crate::diagnostic::or(prev.0, e.0), e.1),
+                                None => e,
+                            });
+                        },
+                    }
+                }
+                (Err(err.unwrap_or_else(|| ("empty alternatives".to_string(), loc))), input, loc)
+            },
+            Expr::Try(e) => match self.run(e, input, loc) {
+                (Ok(t), rest, rest_loc) => (Ok(Tree::Try(Some(Box::new(t)))), rest, rest_loc),
+                (Err(_), _, _) => (Ok(Tree::Try(None)), input, loc),
+            },
+            Expr::Many(e) => {
+                let mut ret = Vec::new();
+                let mut text = input;
+                let mut loc_parse = loc;
+                loop {
+                    match self.run(e, text, loc_parse) {
+                        (Ok(_), _, rest_loc) if rest_loc == loc_parse => return empty_loop(input, loc_parse),
+                        (Ok(t), rest, rest_loc) => {
+                            ret.push(t);
+                            text = rest;
+                            loc_parse = rest_loc;
+                        },
+                        (Err(_), _, _) => break,
+                    }
+                }
+                (Ok(Tree::Many(ret)), text, loc_parse)
+            },
+            Expr::ManySep(e, sep) => {
+                let mut ret = Vec::new();
+                let mut text = input;
+                let mut loc_parse = loc;
+                loop {
+                    let (t, rest, rest_loc) = self.run(e, text, loc_parse);
+                    let Ok(t) = t else {
+                        break;
+                    };
+                    ret.push(t);
+                    match rest.strip_prefix(sep.as_str()) {
+                        Some(_) if rest_loc == loc_parse && sep.is_empty() => return empty_loop(input, loc_parse),
+                        Some(after) => (text, loc_parse) = (after, rest_loc.update(sep).0),
+                        None => {
+                            (text, loc_parse) = (rest, rest_loc);
+                            break;
+                        },
+                    }
+                }
+                (Ok(Tree::Many(ret)), text, loc_parse)
+            },
+            Expr::Label(e, name) => label(name, input, loc, self.run(e, input, loc)),
+        }
+    }
+}
+
+/// the body of `{a}` matched nothing, it would loop forever. the grammar is input,
+/// so it is an error where `many` of a compiled grammar panics
+fn empty_loop(input: &str, loc: Location) -> Output<'_> {
+    (Err(("loop body matched empty input".to_string(), loc)), input, loc)
+}
+
+fn wrap<'a>(name: &str, (ret, rest, loc): Output<'a>) -> Output<'a> {
+    (ret.map(|t| Tree::Rule(name.to_string(), Box::new(t))), rest, loc)
+}
+
+/// like `Parser::label`
+fn label<'a>(name: &str, input: &'a str, loc: Location, ret: Output<'a>) -> Output<'a> {
+    match ret {
+        (Err((_, err_loc)), rest, rest_loc) if err_loc == loc => {
+            (Err((format!("should be {} but get {}", name, input.get(0..1).unwrap_or("")), loc)), rest, rest_loc)
+        },
+        ret => ret,
+    }
+}
+
+/// the builtins of `parser!`, their value is the matched text
+fn builtin<'a>(name: &str, input: &'a str, loc: Location) -> Output<'a> {
+    fn text<'a, O>(input: &'a str, (ret, rest, loc): (Result<O, (String, Location)>, &'a str, Location)) -> Output<'a> {
+        (ret.map(|_| Tree::Text(input[..input.len() - rest.len()].to_string())), rest, loc)
+    }
+    match name {
+        "whitespace" => text(input, crate::whitespace().0(input, loc)),
+        "eof" => text(input, crate::eof().0(input, loc)),
+        "int" => text(input, crate::int().0(input, loc)),
+        "float" => text(input, crate::float().0(input, loc)),
+        "escaped_quoted" => text(input, crate::escaped_quoted().0(input, loc)),
+        _ => (Err((format!("unknown rule {}", name), loc)), input, loc),
+    }
+}
+
+/// a token of the syntax, and the whitespace and `//` comments after it
+macro_rules! sym {
+    ($s: expr) => {
+        (token_base!($s) << regex!(r"(?:\s|//[^\n]*)*"))
+    };
+}
+
+/// `-> (..)` and `-> {..}` of `parser!` hold Rust code, it is skipped up to the closing bracket
+fn code(open: char, input: &str, loc: Location) -> (Result<(), (String, Location)>, &str, Location) {
+    if !input.starts_with(open) {
+        return (Err((format!("should be Rust code in {}", open), loc)), input, loc);
+    }
+    let mut depth = 0;
+    let mut chars = input.char_indices();
+    let mut in_str = false;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if in_str => {
+                chars.next();
+            },
+            '"' => in_str = !in_str,
+            // a char literal, a lifetime has no closing quote
+            '\'' if !in_str => {
+                let after = &input[i + 1..];
+                let len = match after.strip_prefix('\\') {
+                    // `'\''` and `'\\'`, the escaped char is not the end
+                    Some(escaped) => escaped.chars().next()
+                        .and_then(|c| escaped[c.len_utf8()..].find('\'').map(|n| n + 1 + c.len_utf8())),
+                    None => after.chars().next().map(char::len_utf8),
+                };
+                if let Some(len) = len.filter(|len| after[*len..].starts_with('\'')) {
+                    // the chars of the literal and its closing quote
+                    for _ in after[..=len].chars() {
+                        chars.next();
+                    }
+                }
+            },
+            '(' | '[' | '{' if !in_str => depth += 1,
+            ')' | ']' | '}' if !in_str => {
+                depth -= 1;
+                if depth == 0 {
+                    let rest = &input[i + 1..];
+                    return (Ok(()), rest, loc.update(&input[..i + 1]).0);
+                }
+            },
+            _ if depth == 0 => break,
+            _ => {},
+        }
+    }
+    (Err(("unclosed bracket".to_string(), loc)), input, loc)
+}
+
+fn map_code(input: &str, loc: Location) -> (Result<(), (String, Location)>, &str, Location) {
+    code('(', input, loc)
+}
+
+fn action_code(input: &str, loc: Location) -> (Result<(), (String, Location)>, &str, Location) {
+    code('{', input, loc)
+}
+
+/// `"a\"b"` is `a"b`
+fn unescape(s: String) -> String {
+    let mut ret = String::new();
+    let mut chars = s[1..s.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => ret.push('\n'),
+                Some('t') => ret.push('\t'),
+                Some('r') => ret.push('\r'),
+                Some(c) => ret.push(c),
+                None => {},
+            },
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// like `and_then`, the error is at the start of `p`
+fn check<'a, F, O, X>(p: Parser<F, &'a str, O>, m: fn(O) -> Result<X, String>) -> Parser<impl Fn(&'a str, Location) -> (Result<X, (String, Location)>, &'a str, Location) + Copy, &'a str, X>
+where
+    F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy
+{
+    Parser::new(move |input: &'a str, loc: Location| match p.0(input, loc) {
+        (Ok(o), rest, rest_loc) => match m(o) {
+            Ok(x) => (Ok(x), rest, rest_loc),
+            Err(e) => (Err((e, loc)), input, loc),
+        },
+        (Err(e), _, _) => (Err(e), input, loc),
+    })
+}
+
+/// what follows the first item of an alternative
+enum Tail {
+    /// `b: item -> {..}`, the items before the action
+    Action(Vec<(Option<String>, Expr)>),
+    /// `>> item * item`
+    Chain(Vec<(Op, Expr)>),
+}
+
+/// an action keeps the named items, a chain can not name its items
+fn alternative(((name, first), tail): ((Option<String>, Expr), Tail)) -> Result<Expr, String> {
+    match tail {
+        Tail::Action(rest) => Ok(Expr::Seq(std::iter::once((name, first)).chain(rest).map(|(name, e)| match name {
+            Some(_) => e,
+            None => Expr::Skip(Box::new(e)),
+        }).collect())),
+        Tail::Chain(_) if name.is_some() => Err("named items need an action `-> {..}`".to_string()),
+        Tail::Chain(rest) => chain((first, rest)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Op {
+    Right,
+    Product,
+    Left,
+}
+
+/// `a >> b * c << d` is `b * c`, the order of the operators is checked like `parser!` does
+fn chain((first, rest): (Expr, Vec<(Op, Expr)>)) -> Result<Expr, String> {
+    if rest.is_empty() {
+        return Ok(first);
+    }
+    if rest.windows(2).any(|w| w[1].0 < w[0].0) {
+        return Err("`>>`, `*` and `<<` must be in this order, add parentheses".to_string());
+    }
+    let ops: Vec<Op> = rest.iter().map(|(op, _)| *op).collect();
+    let items = std::iter::once(first).chain(rest.into_iter().map(|(_, e)| e));
+    Ok(Expr::Seq(items.enumerate().map(|(i, e)| {
+        let before_right = ops.get(i) == Some(&Op::Right);
+        let after_left = i > 0 && ops[i - 1] == Op::Left;
+        if before_right || after_left {
+            Expr::Skip(Box::new(e))
+        } else {
+            e
+        }
+    }).collect()))
+}
+
+fn ws<'a>() -> Parser!(String) {
+    regex!(r"(?:\s|//[^\n]*)*")
+}
+
+fn ident<'a>() -> Parser!(String) {
+    regex!(r"[A-Za-z_][A-Za-z0-9_]*") << ws()
+}
+
+fn string<'a>() -> Parser!(String) {
+    regex!(r#""(?:\\.|[^"\\])*""#).map(unescape) << ws()
+}
+
+/// `r"a"` and `r#"a"#`
+fn raw<'a>() -> Parser!(String) {
+    (regex!(r##"r#"(?s:.*?)"#"##).map(|r| r[3..r.len() - 2].to_string())
+        | regex!(r#"r"[^"]*""#).map(|r| r[2..r.len() - 1].to_string())) << ws()
+}
+
+/// `#[entry]`, the arguments of an attribute are skipped
+fn attr<'a>() -> Parser!(String) {
+    sym!("#") >> sym!("[") >> ident() << regex!(r"[^\]]*") << sym!("]")
+}
+
+/// `name: Type @ "label" =`
+fn head<'a>() -> Parser!(((Vec<String>, String), Option<String>)) {
+    (attr().many() * (ident() << sym!(":") << regex!(r"[^@=]+")) * (sym!("@") >> string()).to_try()) << sym!("=")
+}
+
+/// the expressions of `parser!`
+fn exprs<'a>(rule: &'a Rule<'a, Expr>) -> Defined<'a, Expr> {
+    let expr = || rule.parser();
+    let paren = sym!("(") >> expr() << sym!(")");
+    let to_try = (sym!("[") >> expr() << sym!("]")).map(|e| Expr::Try(Box::new(e)));
+    let many = (sym!("{") >> (expr() * (sym!("(") >> string() << sym!(")")).to_try()) << sym!("}"))
+        .map(|(e, sep)| match sep {
+            Some(s) => Expr::ManySep(Box::new(e), s),
+            None => Expr::Many(Box::new(e)),
+        });
+    let regex = check(raw(), |r| Expr::regex(&r).map_err(|e| format!("invalid regex: {}", e)));
+    let term = paren | to_try | many | string().map(Expr::Token) | regex | ident().map(Expr::Rule);
+
+    let postfix = (sym!("->") >> Parser::new(map_code) << ws()).map(|_| None) | (sym!("@") >> string()).map(Some);
+    let item = move || (Parser::new(term.0) * Parser::new(postfix.0).to_try()).map(|(e, post)| match post {
+        Some(Some(l)) => Expr::Label(Box::new(e), l),
+        _ => e,
+    });
+
+    // the first item is read once for both forms, trying a whole alternative twice
+    // would read nested brackets again at every level
+    let binding = move || (ident() << sym!(":")).to_try() * item();
+    let action = (binding().many() << sym!("->") << Parser::new(action_code) << ws()).map(Tail::Action);
+    let op = sym!(">>").map(|_| Op::Right) | sym!("*").map(|_| Op::Product) | sym!("<<").map(|_| Op::Left);
+    let chain = (op * item()).many().map(Tail::Chain);
+    let alternative = move || check(binding() * (Parser::new(action.0) | Parser::new(chain.0)), alternative);
+    crate::rule::recursive(rule, |_| (alternative() * (sym!("|") >> alternative()).many()).map(|(first, rest)| {
+        if rest.is_empty() {
+            first
+        } else {
+            Expr::Or(std::iter::once(first).chain(rest).collect())
+        }
+    }))
+}
+
+/// read rules in the syntax of `parser!`
+impl FromStr for Grammar {
+    type Err = (String, Location);
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let exprs_rule = Rule::new();
+        let _exprs = exprs(&exprs_rule);
+        let expr = exprs_rule.parser();
+        let rule = move || head() * Parser::new(expr.0);
+        let ((rules, rest, loc), exceeded) = crate::depth::run(|| (ws() >> rule().many()).0(s, Location::new()));
+        if let Some(e) = exceeded {
+            return Err(e);
+        }
+        if !rest.is_empty() {
+            // the error of the rule that stopped `many`
+            return Err(rule().run_with_out(rest, loc).0.err()
+                .unwrap_or_else(|| (format!("should be a rule but get {}", rest.get(0..1).unwrap_or("")), loc)));
+        }
+        let mut grammar = Grammar::new();
+        for (((attrs, name), label), e) in rules? {
+            let e = if attrs.iter().any(|a| a == "entry") {
+                Expr::Seq(vec![e, Expr::Skip(Box::new(Expr::Rule("eof".to_string())))])
+            } else {
+                e
+            };
+            let e = match label {
+                Some(l) => Expr::Label(Box::new(e), l),
+                None => e,
+            };
+            grammar = grammar.rule(&name, e);
+        }
+        Ok(grammar)
+    }
+}
+
+#[test]
+fn test_dynamic() {
+    let grammar = Grammar::new()
+        .rule("list", Expr::Seq(vec![
+            Expr::Skip(Box::new(Expr::Token("[".to_string()))),
+            Expr::ManySep(Box::new(Expr::Rule("value".to_string())), ",".to_string()),
+            Expr::Skip(Box::new(Expr::Token("]".to_string()))),
+        ]))
+        .rule("value", Expr::Or(vec![Expr::Rule("list".to_string()), Expr::regex("[0-9]+").unwrap()]));
+    let num = |n: &str| Tree::Rule("value".to_string(), Box::new(Tree::Text(n.to_string())));
+    let list = |items| Tree::Rule("list".to_string(), Box::new(Tree::Many(items)));
+    assert_eq!(grammar.parse("list", "[1,[2]]"), Ok(list(vec![
+        num("1"),
+        Tree::Rule("value".to_string(), Box::new(list(vec![num("2")]))),
+    ])));
+    assert_eq!(grammar.parse("list", "x").unwrap_err().0, "should be list but get x");
+}
+
+#[test]
+fn test_dynamic_syntax() {
+    let grammar: Grammar = r#"
+        // comments are skipped
+        #[entry]
+        pairs: Vec<(String, i64)> = "{" >> {pair(",")} << "}"
+        pair: (String, i64) @ "pair" = key:escaped_quoted ":" value:int -> { (key, value) }
+        op: Op = "+" -> (|_| Op::Add) | r"-|\*"
+    "#.parse().unwrap();
+    let pair = |k: &str, v: &str| Tree::Rule("pair".to_string(), Box::new(Tree::Seq(vec![
+        Tree::Text(format!("\"{}\"", k)),
+        Tree::Text(v.to_string()),
+    ])));
+    assert_eq!(grammar.parse("pairs", r#"{"a":1,"b":2}"#), Ok(Tree::Rule("pairs".to_string(), Box::new(
+        Tree::Many(vec![pair("a", "1"), pair("b", "2")])
+    ))));
+    assert_eq!(grammar.parse("pairs", r#"{"a":1} x"#).unwrap_err().0, "should be end of input but get x");
+    assert_eq!(grammar.parse("op", "*"), Ok(Tree::Rule("op".to_string(), Box::new(Tree::Text("*".to_string())))));
+    let e = "a: A = b << c >> d".parse::<Grammar>().err().unwrap();
+    assert!(e.0.contains("must be in this order"));
+
+    // brackets and quotes in char literals are not the end of the code
+    let grammar: Grammar = r#"
+        a: char = "x" -> (|_| '}') >> "y"
+        b: C = x:"x" -> { let c = '\''; let q = '"'; let b = '\\'; let l: &'static str = "{"; (c, '{') }
+    "#.parse().unwrap();
+    assert!(grammar.parse("a", "xy").is_ok());
+    assert!(grammar.parse("b", "x").is_ok());
+}
+
+#[test]
+fn test_dynamic_empty_loop() {
+    let grammar: Grammar = r#"
+        a: A = {["x"]}
+        b: B = {["x"]("")}
+    "#.parse().unwrap();
+    assert_eq!(grammar.parse("a", "xxy").unwrap_err(), ("loop body matched empty input".to_string(), Location { line: 1, col: 3 }));
+    assert_eq!(grammar.parse("b", "xy").unwrap_err(), ("loop body matched empty input".to_string(), Location { line: 1, col: 2 }));
+}
+
+#[test]
+fn test_dynamic_nested() {
+    let nested = |n: usize| format!("a: A = {}\"x\"{}", "(".repeat(n), ")".repeat(n));
+    let grammar: Grammar = nested(100).parse().unwrap();
+    assert!(grammar.parse("a", "x").is_ok());
+    let e = nested(300).parse::<Grammar>().err().unwrap();
+    assert!(e.0.contains("recursion limit"));
+    assert!("a: A = b: c".parse::<Grammar>().is_err());
+}
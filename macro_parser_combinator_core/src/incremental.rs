@@ -0,0 +1,187 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use crate::cst::Cst;
+use crate::location::Location;
+use crate::parser::Parser;
+
+struct Entry {
+    end: usize,
+    /// how far the rule read, the end of input counts as a byte after it
+    read: usize,
+    start_loc: Location,
+    end_loc: Location,
+    node: Cst,
+    /// reused or recorded by the running parse
+    visited: bool,
+}
+
+/// the nodes of `#![cst]` rules from the last parse, by offset, grammar and rule.
+/// after an `edit`, `run_incremental` reuses the nodes whose rules did not read it
+/// and runs again only the rules that do.
+/// a node is kept when the rule read nothing from the edit on, alternatives and `[..]` that
+/// failed count too. a regex is taken to read one byte past its match, or its first byte when it
+/// does not match, so a regex that needs more of the input to decide can give an outdated node.
+/// a parse keeps only the nodes it reused or made, and the nodes inside the reused ones
+#[derive(Default)]
+pub struct Memo {
+    nodes: BTreeMap<(usize, &'static str, &'static str), Entry>,
+    reused: usize,
+    /// the bytes of the nodes the running parse reused
+    spans: Vec<Range<usize>>,
+    /// how far the running rule read
+    furthest: usize,
+}
+
+thread_local! {
+    /// the memo of the running `run_incremental`, with the length of its input
+    static ACTIVE: RefCell<Option<(usize, Memo)>> = const { RefCell::new(None) };
+}
+
+impl Memo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// replace `range` of `text`, in bytes, by `with`, and forget the nodes whose rules read it
+    pub fn edit(&mut self, text: &mut String, range: Range<usize>, with: &str) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.nodes = nodes.into_iter().filter_map(|((start, grammar, rule), mut e)| {
+            if e.read <= range.start {
+                Some(((start, grammar, rule), e))
+            } else if start >= range.end {
+                let moved = |offset: usize| offset - range.end + range.start + with.len();
+                (e.end, e.read) = (moved(e.end), moved(e.read));
+                Some(((moved(start), grammar, rule), e))
+            } else {
+                None
+            }
+        }).collect();
+        text.replace_range(range, with);
+    }
+    /// how many nodes the last `run_incremental` reused
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+    /// how many nodes are kept
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    /// forget the nodes the last parse did not visit, the nodes inside a reused node stay
+    fn prune(&mut self) {
+        let mut spans = std::mem::take(&mut self.spans);
+        spans.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for r in spans {
+            match merged.last_mut() {
+                Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+                _ => merged.push(r),
+            }
+        }
+        self.nodes.retain(|&(start, ..), e| {
+            let i = merged.partition_point(|r| r.start <= start);
+            let inside = i > 0 && e.end <= merged[i - 1].end;
+            std::mem::take(&mut e.visited) || inside
+        });
+    }
+}
+
+/// `loc` of a node that moved from `from` to `to`, the text between them is the same
+fn moved(loc: Location, from: Location, to: Location) -> Location {
+    if loc.line == from.line {
+        Location { line: to.line, col: loc.col - from.col + to.col }
+    } else {
+        Location { line: loc.line - from.line + to.line, col: loc.col }
+    }
+}
+
+fn shift(cst: &mut Cst, from: Location, to: Location) {
+    match cst {
+        Cst::Node { span, children, .. } => {
+            (span.start, span.end) = (moved(span.start, from, to), moved(span.end, from, to));
+            children.iter_mut().for_each(|c| shift(c, from, to));
+        },
+        Cst::Token { span, .. } | Cst::Trivia { span, .. } => {
+            (span.start, span.end) = (moved(span.start, from, to), moved(span.end, from, to));
+        },
+    }
+}
+
+/// the node of `rule` at `input` from the memo, moved to `loc`
+pub(crate) fn reuse<'a>(grammar: &'static str, rule: &'static str, input: &'a str, loc: Location) -> Option<(Cst, &'a str, Location)> {
+    ACTIVE.with(|active| {
+        let mut active = active.borrow_mut();
+        let (len, memo) = active.as_mut()?;
+        let start = len.checked_sub(input.len())?;
+        let e = memo.nodes.get_mut(&(start, grammar, rule))?;
+        if e.start_loc != loc {
+            shift(&mut e.node, e.start_loc, loc);
+            e.end_loc = moved(e.end_loc, e.start_loc, loc);
+            e.start_loc = loc;
+        }
+        e.visited = true;
+        memo.reused += 1;
+        memo.spans.push(start..e.end);
+        memo.furthest = memo.furthest.max(e.read);
+        Some((e.node.clone(), &input[e.end - start..], e.end_loc))
+    })
+}
+
+/// a primitive looked at `n` bytes of `input`, or at its end when it is shorter.
+/// only counts in `run_incremental`
+pub fn read(input: &str, n: usize) {
+    ACTIVE.with(|active| {
+        if let Some((len, memo)) = active.borrow_mut().as_mut() {
+            if let Some(start) = len.checked_sub(input.len()) {
+                memo.furthest = memo.furthest.max(start + n);
+            }
+        }
+    })
+}
+
+/// run a rule, with how far it read. the rule that runs it read that far too
+pub(crate) fn reading<T>(run: impl FnOnce() -> T) -> (T, usize) {
+    let outer = ACTIVE.with(|active| active.borrow_mut().as_mut().map(|(_, memo)| std::mem::take(&mut memo.furthest)));
+    let ret = run();
+    let furthest = ACTIVE.with(|active| match (active.borrow_mut().as_mut(), outer) {
+        (Some((_, memo)), Some(outer)) => {
+            let furthest = memo.furthest;
+            memo.furthest = furthest.max(outer);
+            furthest
+        },
+        _ => 0,
+    });
+    (ret, furthest)
+}
+
+/// keep the node of `rule` that matched `input` up to `rest` and read up to `read`
+pub(crate) fn record((grammar, rule): (&'static str, &'static str), (input, loc): (&str, Location), (rest, end_loc): (&str, Location), read: usize, node: &Cst) {
+    ACTIVE.with(|active| {
+        if let Some((len, memo)) = active.borrow_mut().as_mut() {
+            if let (Some(start), Some(end)) = (len.checked_sub(input.len()), len.checked_sub(rest.len())) {
+                let e = Entry { end, read: read.max(end), start_loc: loc, end_loc, node: node.clone(), visited: true };
+                memo.nodes.insert((start, grammar, rule), e);
+            }
+        }
+    })
+}
+
+impl<'a, F, O> Parser<F, &'a str, O>
+where
+    F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy
+{
+    /// `run` that reuses the nodes of `memo` and keeps the new ones in it, instead of the ones it did not visit
+    pub fn run_incremental(&self, input: &'a str, memo: &mut Memo) -> Result<O, (String, Location)> {
+        memo.reused = 0;
+        memo.furthest = 0;
+        let outer = ACTIVE.with(|active| active.replace(Some((input.len(), std::mem::take(memo)))));
+        let ret = self.run(input);
+        if let Some((_, m)) = ACTIVE.with(|active| active.replace(outer)) {
+            *memo = m;
+        }
+        memo.prune();
+        ret
+    }
+}
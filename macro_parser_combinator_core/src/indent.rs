@@ -0,0 +1,114 @@
+use crate::location::Location;
+use crate::parser::Parser;
+
+/// skip whitespace, for the column checks
+fn skip(input: &str, loc: Location) -> (&str, Location) {
+    let (_, input, loc) = crate::whitespace().0(input, loc);
+    (input, loc)
+}
+
+impl<'a, F, O> Parser<F, &'a str, O>
+where
+    F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy
+{
+    /// many items that all start at the column of the first one, each on a new line
+    pub fn aligned(self) -> Parser<impl Fn(&'a str, Location) -> (Result<Vec<O>, (String, Location)>, &'a str, Location) + Copy, &'a str, Vec<O>> {
+        let f = move |input: &'a str, loc: Location| {
+            let mut ret = Vec::new();
+            let mut text = input;
+            let mut loc_parse = loc;
+            let mut start: Option<Location> = None;
+            loop {
+                let (item_input, item_loc) = skip(text, loc_parse);
+                if let Some(s) = start {
+                    if item_loc.col != s.col || item_loc.line <= s.line {
+                        break;
+                    }
+                }
+                let parse = self.0(item_input, item_loc);
+                match parse.0 {
+                    Ok(item) => {
+                        ret.push(item);
+                        start = Some(item_loc);
+                        text = parse.1;
+                        loc_parse = parse.2;
+                    },
+                    Err(_) => break,
+                }
+            }
+            (Ok(ret), text, loc_parse)
+        };
+        Parser(f, std::marker::PhantomData::<&'a str>, std::marker::PhantomData::<Vec<O>>)
+    }
+    /// offside rule: `self` as the header, then aligned items on the next lines,
+    /// indented further than the header
+    pub fn indented_block<F2, O2>(self, item: Parser<F2, &'a str, O2>) -> Parser<impl Fn(&'a str, Location) -> (Result<(O, Vec<O2>), (String, Location)>, &'a str, Location) + Copy, &'a str, (O, Vec<O2>)>
+    where
+        F2: Fn(&'a str, Location) -> (Result<O2, (String, Location)>, &'a str, Location) + Copy
+    {
+        let items = item.aligned();
+        let f = move |input: &'a str, loc: Location| {
+            let (header_input, header_loc) = skip(input, loc);
+            let (header, block_input, block_loc) = self.0(header_input, header_loc);
+            let header = match header {
+                Ok(h) => h,
+                Err(e) => return (Err(e), input, loc),
+            };
+            let (item_input, item_loc) = skip(block_input, block_loc);
+            if item_loc.line <= header_loc.line || item_loc.col <= header_loc.col {
+                return (Err((format!("should be indented block after line {}", header_loc.line), item_loc)), input, loc);
+            }
+            match items.0(item_input, item_loc) {
+                (Ok(v), ret_input, ret_loc) if !v.is_empty() => (Ok((header, v)), ret_input, ret_loc),
+                _ => (Err((format!("should be indented block after line {}", header_loc.line), item_loc)), input, loc),
+            }
+        };
+        Parser(f, std::marker::PhantomData::<&'a str>, std::marker::PhantomData::<(O, Vec<O2>)>)
+    }
+    /// like `*`, but `rhs` must start on the line where `self` starts
+    pub fn same_line<F2, O2>(self, rhs: Parser<F2, &'a str, O2>) -> Parser<impl Fn(&'a str, Location) -> (Result<(O, O2), (String, Location)>, &'a str, Location) + Copy, &'a str, (O, O2)>
+    where
+        F2: Fn(&'a str, Location) -> (Result<O2, (String, Location)>, &'a str, Location) + Copy
+    {
+        let f = move |input: &'a str, loc: Location| {
+            let (left_input, left_loc) = skip(input, loc);
+            let (left, next_input, next_loc) = self.0(left_input, left_loc);
+            let left = match left {
+                Ok(l) => l,
+                Err(e) => return (Err(e), input, loc),
+            };
+            let (right_input, right_loc) = skip(next_input, next_loc);
+            if right_loc.line != left_loc.line {
+                return (Err((format!("should be on line {}", left_loc.line), right_loc)), input, loc);
+            }
+            match rhs.0(right_input, right_loc) {
+                (Ok(r), ret_input, ret_loc) => (Ok((left, r)), ret_input, ret_loc),
+                (Err(e), _, _) => (Err(e), input, loc),
+            }
+        };
+        Parser(f, std::marker::PhantomData::<&'a str>, std::marker::PhantomData::<(O, O2)>)
+    }
+}
+
+pub fn aligned<'a, F, O>(item: Parser<F, &'a str, O>) -> Parser<impl Fn(&'a str, Location) -> (Result<Vec<O>, (String, Location)>, &'a str, Location) + Copy, &'a str, Vec<O>>
+where
+    F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy
+{
+    item.aligned()
+}
+
+pub fn indented_block<'a, F1, F2, O1, O2>(header: Parser<F1, &'a str, O1>, item: Parser<F2, &'a str, O2>) -> Parser<impl Fn(&'a str, Location) -> (Result<(O1, Vec<O2>), (String, Location)>, &'a str, Location) + Copy, &'a str, (O1, Vec<O2>)>
+where
+    F1: Fn(&'a str, Location) -> (Result<O1, (String, Location)>, &'a str, Location) + Copy,
+    F2: Fn(&'a str, Location) -> (Result<O2, (String, Location)>, &'a str, Location) + Copy,
+{
+    header.indented_block(item)
+}
+
+pub fn same_line<'a, F1, F2, O1, O2>(left: Parser<F1, &'a str, O1>, right: Parser<F2, &'a str, O2>) -> Parser<impl Fn(&'a str, Location) -> (Result<(O1, O2), (String, Location)>, &'a str, Location) + Copy, &'a str, (O1, O2)>
+where
+    F1: Fn(&'a str, Location) -> (Result<O1, (String, Location)>, &'a str, Location) + Copy,
+    F2: Fn(&'a str, Location) -> (Result<O2, (String, Location)>, &'a str, Location) + Copy,
+{
+    left.same_line(right)
+}
@@ -4,22 +4,93 @@ extern crate lazy_static;
 
 pub mod location;
 pub mod parser;
+pub mod diagnostic;
+pub mod indent;
+pub mod depth;
+pub mod rule;
+pub mod boxed;
+pub mod dynamic;
+pub mod cst;
+pub mod state;
+#[cfg(feature = "incremental")]
+pub mod incremental;
+#[cfg(feature = "streaming")]
+pub mod streaming;
+#[cfg(feature = "streaming")]
+pub mod reader;
+#[cfg(feature = "context")]
+mod context;
+#[cfg(feature = "trace")]
+mod trace;
 
 pub use regex::Regex;
 pub use lazy_static::lazy_static;
 
 pub use crate::location::Location;
 pub use crate::parser::Parser;
+pub use crate::diagnostic::Diagnostic;
+pub use crate::indent::{aligned, indented_block, same_line};
+pub use crate::depth::{set_recursion_limit, recursion_limit};
+pub use crate::rule::{Rule, Defined, recursive};
+pub use crate::boxed::{BoxedParser, SyncParser};
+pub use crate::cst::{Cst, Span};
+pub use crate::state::{Checkpoint, Cloned};
+#[cfg(feature = "incremental")]
+pub use crate::incremental::Memo;
+#[cfg(feature = "streaming")]
+pub use crate::streaming::Partial;
+#[cfg(feature = "streaming")]
+pub use crate::reader::Reader;
+
+/// a primitive looked at `n` bytes of the input, for `run_incremental`.
+/// nothing without the `incremental` feature
+#[cfg(feature = "incremental")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! incremental_read {
+    ($input: expr, $n: expr) => {
+        $crate::incremental::read($input, $n)
+    };
+}
+
+#[cfg(not(feature = "incremental"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! incremental_read {
+    ($input: expr, $n: expr) => {};
+}
+
+/// a primitive hit the end of the input and needs `n` more bytes, for `run_partial`.
+/// nothing without the `streaming` feature
+#[cfg(feature = "streaming")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! streaming_need {
+    ($n: expr) => {
+        $crate::streaming::need($n)
+    };
+}
+
+#[cfg(not(feature = "streaming"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! streaming_need {
+    ($n: expr) => {};
+}
 
 #[macro_export]
 macro_rules! char {
     ($p: expr) => {
         {
             fn f(input: &str, loc: Location) -> (Result<&str, (String, Location)>, &str, Location) {
+                $crate::incremental_read!(input, $p.len_utf8());
                 if let Some(o) = input.strip_prefix($p) {
                     let loc_parse = loc.update_char($p);
                     (Ok(o), o, loc_parse.0)
                 } else {
+                    if input.is_empty() {
+                        $crate::streaming_need!($p.len_utf8());
+                    }
                     (
                         Err((format!("should be char {} but get {}",
                             $p,
@@ -39,10 +110,14 @@ macro_rules! token_base {
     ($p: expr) => {
         {
             fn f(input: &str, loc: Location) -> (Result<&str, (String, Location)>, &str, Location) {
+                $crate::incremental_read!(input, $p.len());
                 if let Some(o) = input.strip_prefix($p) {
                     let loc_parse = loc.update($p);
                     (Ok($p), o, loc_parse.0)
                 } else {
+                    if $p.starts_with(input) {
+                        $crate::streaming_need!($p.len() - input.len());
+                    }
                     (
                         Err((format!("should be token {} but get {}",
                             $p,
@@ -77,9 +152,12 @@ macro_rules! whitespace {
                         Some('\n') => {b.next();loc.col = 1;loc.line += 1;},
                         Some('\r') => {b.next();loc.col = 1;loc.line += 1;},
                         Some('\t') => {b.next();loc.col += 1;},
+                        None => {$crate::streaming_need!(1);break;}
                         _ => {break;}
                     }
                 }
+                // the char that stopped it, or the end of the input
+                $crate::incremental_read!(input, input.len() - b.as_str().len() + 1);
                 (Ok(""), b.as_str(), loc)
             }
             Parser(f, std::marker::PhantomData::<&str>, std::marker::PhantomData::<&str>)
@@ -91,6 +169,33 @@ pub fn whitespace<'a>() -> Parser!() {
     whitespace!()
 }
 
+#[macro_export]
+macro_rules! eof {
+    () => {
+        {
+            fn f(input: &str, loc: Location) -> (Result<&str, (String, Location)>, &str, Location) {
+                $crate::incremental_read!(input, 1);
+                if input.is_empty() {
+                    $crate::streaming_need!(1);
+                    (Ok(input), input, loc)
+                } else {
+                    (
+                        Err((format!("should be end of input but get {}",
+                            input.get(0..1).unwrap_or("")), loc)),
+                        input,
+                        loc
+                    )
+                }
+            }
+            Parser(f, std::marker::PhantomData::<&str>, std::marker::PhantomData::<&str>)
+        }
+    };
+}
+
+pub fn eof<'a>() -> Parser!() {
+    eof!()
+}
+
 #[macro_export]
 macro_rules! token {
     ($p: expr) => {
@@ -109,6 +214,11 @@ macro_rules! regex {
                 }
                 let cap = RE.find(input).map(|x| x.as_str());
                 let o = cap.and_then(|x| input.strip_prefix(x));
+                $crate::incremental_read!(input, input.len() - o.unwrap_or(input).len() + 1);
+                if o.map_or(input.is_empty(), str::is_empty) {
+                    // a longer input could match, or match more
+                    $crate::streaming_need!(1);
+                }
                 match o {
                     Some(output) => {
                         let loc_parse = loc.update(cap.unwrap());
@@ -160,10 +270,14 @@ macro_rules! sep {
     ($p: expr) => {
         {
             fn f(input: &str, loc: Location) -> (Option<&str>, Location) {
+                $crate::incremental_read!(input, $p.len());
                 if let Some(o) = input.strip_prefix($p) {
                     let loc_parse = loc.update($p);
                     (Some(o), loc_parse.0)
                 } else {
+                    if $p.starts_with(input) {
+                        $crate::streaming_need!($p.len() - input.len());
+                    }
                     (
                         None,
                         loc
@@ -179,7 +293,13 @@ macro_rules! sep {
 macro_rules! tobox {
     ($p: expr) => {
         {
-            let f = |input, loc: Location| Box::new($p.0)(input, loc);
+            let f = move |input, loc: Location| {
+                let _depth = match $crate::depth::Depth::enter(loc) {
+                    Ok(d) => d,
+                    Err(e) => return (Err(e), input, loc),
+                };
+                ($p.0)(input, loc)
+            };
             Parser::new(f)
         }
     };
@@ -187,10 +307,10 @@ macro_rules! tobox {
 
 #[macro_export]
 macro_rules! Parser {
-    ($t: tt) => {
-        Parser<impl Fn(&'a str, Location) -> (Result<$t, (String, Location)>, &'a str, Location) + Copy, &'a str, $t>
-    };
     () => {
         Parser<impl Fn(&'a str, Location) -> (Result<&'a str, (String, Location)>, &'a str, Location) + Copy, &'a str, &'a str>
+    };
+    ($t: ty) => {
+        Parser<impl Fn(&'a str, Location) -> (Result<$t, (String, Location)>, &'a str, Location) + Copy, &'a str, $t>
     }
 }
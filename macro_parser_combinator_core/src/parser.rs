@@ -1,6 +1,8 @@
+use std::cell::RefCell;
 use std::marker::PhantomData;
 use std::ops::{Mul, Shr, Shl, BitOr};
 use crate::location::Location;
+use crate::state::Checkpoint;
 
 #[derive(Copy, Clone)]
 pub struct Parser<F: Copy, I, O>(pub F, pub PhantomData<I>, pub PhantomData<O>);
@@ -13,7 +15,10 @@ where
         Self(f, PhantomData::<I>, PhantomData::<O>)
     }
     pub fn run_with_out(&self, input: I, loc: Location) -> (Result<O, (String, Location)>, I, Location) {
-        self.0(input, loc)
+        match crate::depth::run(|| self.0(input, loc)) {
+            (_, Some(e)) => (Err(e), input, loc),
+            (ret, None) => ret,
+        }
     }
     pub fn run(&self, input: I) -> Result<O, (String, Location)> {
         self.run_with_out(input, Location::new()).0
@@ -25,6 +30,8 @@ where
         };
         Parser(f, std::marker::PhantomData::<I>, std::marker::PhantomData::<Option<O>>)
     }
+    /// the items until one fails. an item that matches empty input panics in debug builds,
+    /// the message names the rule only with the `context` feature
     pub fn many(self) -> Parser<impl Fn(I, Location) -> (Result<Vec<O>, (String, Location)>, I, Location) + Copy, I, Vec<O>> {
         let f = move |input: I, loc: Location| {
             let mut ret = Vec::new();
@@ -33,6 +40,10 @@ where
             loop {
                 let parse = self.0(text, loc_parse);
                 match parse.0 {
+                    Ok(_) if parse.2 == loc_parse => {
+                        no_progress("many", loc_parse);
+                        break;
+                    },
                     Ok(item) => {
                         ret.push(item);
                         text = parse.1;
@@ -45,6 +56,8 @@ where
         };
         Parser(f, std::marker::PhantomData::<I>, std::marker::PhantomData::<Vec<O>>)
     }
+    /// the items with `sep` between them. an item and a separator that match empty input panic
+    /// in debug builds like `many`
     pub fn many_sep<Fs>(self, sep: Fs) -> Parser<impl Fn(I, Location) -> (Result<Vec<O>, (String, Location)>, I, Location) + Copy, I, Vec<O>>
     where
         Fs: Fn(I, Location) -> (Option<I>, Location) + Copy
@@ -60,6 +73,12 @@ where
                         ret.push(item);
                         let jump_sep = sep(parse.1, parse.2);
                         match jump_sep.0 {
+                            Some(_) if jump_sep.1 == loc_parse => {
+                                no_progress("many_sep", loc_parse);
+                                text = parse.1;
+                                loc_parse = parse.2;
+                                break
+                            },
                             Some(t) => {
                                 text = t;
                                 loc_parse = jump_sep.1;
@@ -99,6 +118,153 @@ where
         Parser(f, std::marker::PhantomData::<I>, std::marker::PhantomData::<X>)
  
     }
+    /// like `map`, with mutable access to the user state
+    pub fn map_state<'s, S, M, X>(self, state: &'s RefCell<S>, m: M) -> Parser<impl Fn(I, Location) -> (Result<X, (String, Location)>, I, Location) + Copy + use<'s, F, I, O, S, M, X>, I, X>
+    where
+        M: Fn(&mut S, O) -> X + Copy
+    {
+        let f = move |input: I, loc: Location| {
+            let (ret, ret_input, ret_loc) = self.0(input, loc);
+            (ret.map(|o| m(&mut state.borrow_mut(), o)), ret_input, ret_loc)
+        };
+        Parser(f, std::marker::PhantomData::<I>, std::marker::PhantomData::<X>)
+    }
+    /// like `and_then`, with mutable access to the user state
+    pub fn and_then_state<'s, S, M, X>(self, state: &'s RefCell<S>, m: M) -> Parser<impl Fn(I, Location) -> (Result<X, (String, Location)>, I, Location) + Copy + use<'s, F, I, O, S, M, X>, I, X>
+    where
+        M: Fn(&mut S, O) -> Result<X, (String, Location)> + Copy
+    {
+        let f = move |input: I, loc: Location| {
+            let (ret, ret_input, ret_loc) = self.0(input, loc);
+            (ret.and_then(|o| m(&mut state.borrow_mut(), o)), ret_input, ret_loc)
+        };
+        Parser(f, std::marker::PhantomData::<I>, std::marker::PhantomData::<X>)
+    }
+    /// restore the user state to a checkpoint if the parser fails, so changes made by a failed branch are undone
+    pub fn rollback<'s, S: Checkpoint>(self, state: &'s RefCell<S>) -> Parser<impl Fn(I, Location) -> (Result<O, (String, Location)>, I, Location) + Copy + use<'s, F, I, O, S>, I, O> {
+        let f = move |input: I, loc: Location| {
+            let saved = state.borrow().checkpoint();
+            let ret = self.0(input, loc);
+            if ret.0.is_err() {
+                state.borrow_mut().restore(saved);
+            }
+            ret
+        };
+        Parser(f, std::marker::PhantomData::<I>, std::marker::PhantomData::<O>)
+    }
+    /// push the rule name while running, so errors carry a trace like `(in obj > key_value > value)`.
+    /// does nothing without the `context` feature
+    #[cfg(feature = "context")]
+    pub fn context(self, name: &'static str) -> Parser<impl Fn(I, Location) -> (Result<O, (String, Location)>, I, Location) + Copy, I, O> {
+        let f = move |input: I, loc: Location| {
+            crate::context::enter(name);
+            let (ret, ret_input, ret_loc) = self.0(input, loc);
+            (crate::context::exit(loc, ret), ret_input, ret_loc)
+        };
+        Parser(f, std::marker::PhantomData::<I>, std::marker::PhantomData::<O>)
+    }
+    #[cfg(not(feature = "context"))]
+    pub fn context(self, _name: &'static str) -> Self {
+        self
+    }
+}
+
+/// the item of a repetition matched without consuming input, so it would loop forever.
+/// panic in debug builds, naming the rule with the `context` feature, the repetition stops in release builds
+#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+pub(crate) fn no_progress(combinator: &str, loc: Location) {
+    #[cfg(debug_assertions)]
+    {
+        #[cfg(feature = "context")]
+        let rule = crate::context::current()
+            .map(|r| format!(" in rule {}", r))
+            .unwrap_or_default();
+        #[cfg(not(feature = "context"))]
+        let rule = "";
+        panic!("{} at {}:{}{} matched empty input and would loop forever", combinator, loc.line, loc.col, rule);
+    }
+}
+
+impl<'a, F, O> Parser<F, &'a str, O>
+where
+    F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy
+{
+    /// like `run`, but fails at the first unconsumed character
+    pub fn run_complete(&self, input: &'a str) -> Result<O, (String, Location)> {
+        match self.run_with_out(input, Location::new()) {
+            (Ok(_), rest, loc) if !rest.is_empty() => {
+                Err((format!("should be end of input but get {}", rest.get(0..1).unwrap_or("")), loc))
+            },
+            (ret, _, _) => ret,
+        }
+    }
+    /// name the parser in errors. the error is only replaced when nothing was matched,
+    /// so errors from deeper inside are kept
+    pub fn label(self, name: &'static str) -> Parser<impl Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy, &'a str, O> {
+        let f = move |input: &'a str, loc: Location| {
+            match self.0(input, loc) {
+                (Err((_, err_loc)), ret_input, ret_loc) if err_loc == loc => {
+                    (Err((format!("should be {} but get {}", name, input.get(0..1).unwrap_or("")), loc)), ret_input, ret_loc)
+                },
+                ret => ret,
+            }
+        };
+        Parser(f, std::marker::PhantomData::<&'a str>, std::marker::PhantomData::<O>)
+    }
+    /// print rule entry and exit as an indented tree on stderr.
+    /// does nothing without the `trace` feature
+    #[cfg(feature = "trace")]
+    pub fn trace(self, name: &'static str) -> Parser<impl Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy, &'a str, O> {
+        let f = move |input: &'a str, loc: Location| {
+            crate::trace::enter(name, loc);
+            let (ret, ret_input, ret_loc) = self.0(input, loc);
+            match &ret {
+                Ok(_) => crate::trace::exit(name, ret_loc, Ok(&input[..input.len() - ret_input.len()])),
+                Err((msg, err_loc)) => crate::trace::exit(name, *err_loc, Err(msg)),
+            }
+            (ret, ret_input, ret_loc)
+        };
+        Parser(f, std::marker::PhantomData::<&'a str>, std::marker::PhantomData::<O>)
+    }
+    #[cfg(not(feature = "trace"))]
+    pub fn trace(self, _name: &'static str) -> Self {
+        self
+    }
+    /// on failure, skip input until `sync` matches and return the error as a node
+    /// instead of failing. without a sync point the rest of the input is skipped,
+    /// it only fails at the end of input, where there is nothing to skip
+    pub fn recover<Fs>(self, sync: Fs) -> Parser<impl Fn(&'a str, Location) -> (Result<Result<O, (String, Location)>, (String, Location)>, &'a str, Location) + Copy, &'a str, Result<O, (String, Location)>>
+    where
+        Fs: Fn(&'a str, Location) -> (Option<&'a str>, Location) + Copy
+    {
+        let f = move |input: &'a str, loc: Location| {
+            let (ret, ret_input, ret_loc) = self.0(input, loc);
+            match ret {
+                Ok(o) => (Ok(Ok(o)), ret_input, ret_loc),
+                Err(e) => {
+                    let mut text = input;
+                    let mut loc_skip = loc;
+                    if input.is_empty() {
+                        return (Err(e), input, loc);
+                    }
+                    loop {
+                        if text.is_empty() {
+                            break (Ok(Err(e)), text, loc_skip);
+                        }
+                        if let (Some(t), loc_sync) = sync(text, loc_skip) {
+                            break (Ok(Err(e)), t, loc_sync);
+                        }
+                        let mut chars = text.chars();
+                        if let Some(c) = chars.next() {
+                            loc_skip = loc_skip.update_char(c).0;
+                        }
+                        text = chars.as_str();
+                    }
+                },
+            }
+        };
+        Parser(f, std::marker::PhantomData::<&'a str>, std::marker::PhantomData::<Result<O, (String, Location)>>)
+    }
 }
 
 /*pub fn y_combinator<F1, F2, I, O>(f: &dyn Fn(Parser<F1, I, O>) -> Parser<F2, I, O>)
@@ -204,7 +370,7 @@ where
                     match ret.0 {
                         Ok(r0) => (Ok(r0), ret.1, ret.2),
                         Err(right_err) => (
-                            Err((format!("{} or {}", left_err.0, right_err.0), right_err.1)),
+                            Err((crate::diagnostic::or(left_err.0, right_err.0), right_err.1)),
                             ret.1, ret.2)
                     }
                 },
@@ -213,3 +379,41 @@ where
         Parser(f, std::marker::PhantomData::<I>, std::marker::PhantomData::<O>)
     }
 }
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "many at 1:1 matched empty input")]
+fn test_no_progress() {
+    let _ = crate::whitespace().many().run("a");
+}
+
+#[cfg(all(debug_assertions, feature = "context"))]
+#[test]
+#[should_panic(expected = "many at 1:1 in rule spaces matched empty input")]
+fn test_no_progress_rule() {
+    let _ = crate::whitespace().many().context("spaces").run("a");
+}
+
+#[cfg(all(debug_assertions, not(feature = "context")))]
+#[test]
+fn test_no_progress_no_rule() {
+    // the rule is only known with the `context` feature
+    let panic = std::panic::catch_unwind(|| crate::whitespace().many().context("spaces").run("a")).unwrap_err();
+    assert_eq!(panic.downcast_ref::<String>().map(String::as_str), Some("many at 1:1 matched empty input and would loop forever"));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "many_sep at 1:1 matched empty input")]
+fn test_sep_no_progress() {
+    let _ = crate::whitespace().many_sep(crate::sep!("")).run("a");
+}
+
+#[test]
+fn test_sep_progress() {
+    let ret = crate::int().many_sep(crate::sep!(",")).run("1,2,x");
+    assert_eq!(ret.ok(), Some(vec![1, 2]));
+    // the items match empty input, the separators move on
+    let ret = crate::whitespace().many_sep(crate::sep!(",")).run_with_out(",,a", Location::new());
+    assert_eq!((ret.0.map(|v| v.len()), ret.1), (Ok(3), "a"));
+}
@@ -0,0 +1,318 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::marker::PhantomData;
+use std::path::Path;
+use crate::location::Location;
+use crate::streaming::streamed;
+
+const CHUNK: usize = 64 * 1024;
+
+/// input read in chunks, the text of a chunk that ends inside a char waits for the next one.
+/// `values` parses it value by value and drops what was parsed,
+/// so a large input is never in memory at once
+pub struct Reader<R> {
+    inner: R,
+    text: String,
+    /// the bytes of a char that is not read completely
+    rest: Vec<u8>,
+    /// what a read goes into, allocated once
+    buf: Vec<u8>,
+    /// a file mapped in memory instead of `text`, `start` is in it
+    mapped: Option<Mapped>,
+    start: usize,
+    loc: Location,
+    eof: bool,
+}
+
+impl Reader<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Reader::new(File::open(path)?))
+    }
+    /// the file mapped in memory instead of read, on 64-bit linux and macos, read on the others.
+    /// the system reads its pages when they are parsed and can drop them after,
+    /// the text is checked to be utf-8 as it is parsed
+    /// ```ignore
+    /// let lines = unsafe { Reader::map("server.log") }?.values(|input, loc| line().run_with_out(input, loc));
+    /// ```
+    ///
+    /// # Safety
+    /// the file must not be written or truncated, by this or another process, while the reader lives.
+    /// a truncated file kills the process with `SIGBUS`, a written one breaks the utf-8 check
+    pub unsafe fn map<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = Reader::open(path)?;
+        reader.mapped = Mapped::new(&reader.inner)?;
+        Ok(reader)
+    }
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Reader { inner, text: String::new(), rest: Vec::new(), buf: Vec::new(), mapped: None, start: 0, loc: Location::new(), eof: false }
+    }
+    /// the values parsed one after another by `run` until the end of the input,
+    /// `run` makes the parser again for every value, see `run_reader!`
+    pub fn values<O, C>(self, run: C) -> Values<R, O, C>
+    where
+        C: for<'b> Fn(&'b str, Location) -> (Result<O, (String, Location)>, &'b str, Location)
+    {
+        Values { reader: self, run, failed: false, output: PhantomData }
+    }
+    /// the text that is not parsed yet
+    fn input(&self) -> &str {
+        match &self.mapped {
+            Some(m) => &m.checked()[self.start..],
+            None => &self.text[self.start..],
+        }
+    }
+    /// read at least `needed` more bytes and as many as wait to be parsed, or up to the end of the input.
+    /// a value is parsed again from its start after a read, reading as much again as it has
+    /// parses it a logarithmic number of times instead of once a chunk
+    fn fill(&mut self, needed: usize) -> Result<(), (String, Location)> {
+        if let Some(m) = &mut self.mapped {
+            self.eof = m.check(needed.max(m.checked - self.start)).map_err(|_| ("invalid utf-8".to_string(), self.loc))?;
+            return Ok(());
+        }
+        let wanted = needed.max(self.text.len() - self.start);
+        let mut read = 0;
+        self.buf.resize(CHUNK, 0);
+        while read < wanted {
+            let n = loop {
+                match self.inner.read(&mut self.buf) {
+                    Ok(n) => break n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err((e.to_string(), self.loc)),
+                }
+            };
+            if n == 0 {
+                self.eof = true;
+                break;
+            }
+            self.rest.extend_from_slice(&self.buf[..n]);
+            read += n;
+        }
+        let valid = match std::str::from_utf8(&self.rest) {
+            Ok(s) => s.len(),
+            // the text before an invalid char is parsed first
+            Err(e) if e.error_len().is_none() || e.valid_up_to() > 0 => e.valid_up_to(),
+            Err(_) => return Err(("invalid utf-8".to_string(), self.loc)),
+        };
+        if self.start > CHUNK && self.start * 2 > self.text.len() {
+            self.text.drain(..self.start);
+            self.start = 0;
+        }
+        // checked by from_utf8
+        self.text.push_str(std::str::from_utf8(&self.rest[..valid]).unwrap());
+        self.rest.drain(..valid);
+        match self.eof && !self.rest.is_empty() {
+            true => Err(("invalid utf-8 at the end of input".to_string(), self.loc)),
+            false => Ok(()),
+        }
+    }
+}
+
+/// a file mapped read-only in memory, the text up to `checked` is utf-8
+struct Mapped {
+    ptr: *const u8,
+    len: usize,
+    checked: usize,
+}
+
+#[cfg(all(any(target_os = "linux", target_os = "macos"), target_pointer_width = "64"))]
+mod sys {
+    use std::ffi::c_void;
+    // the same on linux and macos
+    pub const PROT_READ: i32 = 1;
+    pub const MAP_PRIVATE: i32 = 2;
+    extern "C" {
+        // `off_t` is 64 bits on these targets
+        pub fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> i32;
+    }
+}
+
+// the mapping is read-only
+unsafe impl Send for Mapped {}
+unsafe impl Sync for Mapped {}
+
+impl Mapped {
+    /// a private read-only mapping of the whole file, unmapped on drop.
+    /// an empty file needs none, an empty mapping is an error
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), target_pointer_width = "64"))]
+    fn new(file: &File) -> io::Result<Option<Self>> {
+        use std::os::unix::io::AsRawFd;
+        let len = usize::try_from(file.metadata()?.len()).map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        if len == 0 {
+            return Ok(None);
+        }
+        let ptr = unsafe { sys::mmap(std::ptr::null_mut(), len, sys::PROT_READ, sys::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(Mapped { ptr: ptr as *const u8, len, checked: 0 }))
+    }
+    #[cfg(not(all(any(target_os = "linux", target_os = "macos"), target_pointer_width = "64")))]
+    fn new(_: &File) -> io::Result<Option<Self>> {
+        Ok(None)
+    }
+    fn bytes(&self) -> &[u8] {
+        // the mapping lives as long as `self`, `Reader::map` says the file does not change
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+    fn checked(&self) -> &str {
+        // checked by `check`
+        unsafe { std::str::from_utf8_unchecked(&self.bytes()[..self.checked]) }
+    }
+    /// check at least `wanted` more bytes, or up to the end of the file, and whether it is the end
+    fn check(&mut self, wanted: usize) -> Result<bool, std::str::Utf8Error> {
+        let end = self.len.min(self.checked + wanted.max(CHUNK));
+        let valid = match std::str::from_utf8(&self.bytes()[self.checked..end]) {
+            Ok(s) => s.len(),
+            Err(e) if (e.error_len().is_none() && end < self.len) || e.valid_up_to() > 0 => e.valid_up_to(),
+            Err(e) => return Err(e),
+        };
+        self.checked += valid;
+        Ok(self.checked == self.len)
+    }
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        #[cfg(all(any(target_os = "linux", target_os = "macos"), target_pointer_width = "64"))]
+        unsafe {
+            sys::munmap(self.ptr as *mut _, self.len);
+        }
+    }
+}
+
+/// the iterator of `Reader::values`, it stops after the first error
+pub struct Values<R, O, C> {
+    reader: Reader<R>,
+    run: C,
+    failed: bool,
+    output: PhantomData<O>,
+}
+
+impl<R, O, C> Iterator for Values<R, O, C>
+where
+    R: Read,
+    C: for<'b> Fn(&'b str, Location) -> (Result<O, (String, Location)>, &'b str, Location)
+{
+    type Item = Result<O, (String, Location)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let ret = loop {
+            let r = &mut self.reader;
+            let input = r.input();
+            if input.is_empty() && r.eof {
+                return None;
+            }
+            let (((ret, rest, loc), needed), exceeded) = crate::depth::run(|| match r.eof {
+                true => ((self.run)(input, r.loc), None),
+                false => streamed(|| (self.run)(input, r.loc)),
+            });
+            if let Some(e) = exceeded {
+                break Err(e);
+            }
+            if let Some(needed) = needed {
+                match r.fill(needed) {
+                    Ok(()) => continue,
+                    Err(e) => break Err(e),
+                }
+            }
+            match ret {
+                Ok(_) if rest.len() == input.len() => {
+                    break Err((format!("should be end of input but get {}", rest.get(0..1).unwrap_or("")), loc));
+                },
+                Ok(o) => {
+                    r.start += input.len() - rest.len();
+                    r.loc = loc;
+                    break Ok(o);
+                },
+                Err(e) => break Err(e),
+            }
+        };
+        self.failed = ret.is_err();
+        Some(ret)
+    }
+}
+
+/// the values parsed by `p` one after another from a `Read`, `p` is made again for every value.
+/// it is a macro and not a method of `Parser`, a parser only takes input of the lifetime it was
+/// made for, and the text of a value is dropped after it is parsed
+/// ```ignore
+/// for line in run_reader!(line(), std::io::stdin()) { ... }
+/// ```
+#[macro_export]
+macro_rules! run_reader {
+    ($p: expr, $r: expr) => {
+        $crate::reader::Reader::new($r).values(move |input, loc| $p.run_with_out(input, loc))
+    };
+}
+
+/// `run_reader!` on a file, it is `Err` when the file can not be opened
+#[macro_export]
+macro_rules! run_file {
+    ($p: expr, $path: expr) => {
+        $crate::reader::Reader::open($path).map(|r| r.values(move |input, loc| $p.run_with_out(input, loc)))
+    };
+}
+
+#[test]
+fn test_reader() {
+    use crate::{char, regex, lazy_static, Regex, Parser};
+    /// a few bytes at a time, so chars are split between reads
+    struct Trickle<'a>(&'a [u8]);
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+    fn line<'a>() -> Parser!(String) {
+        regex!("[^\n]*") << char!('\n')
+    }
+    let lines: Result<Vec<String>, _> = run_reader!(line(), Trickle("héllo\nwörld\n日本語\n".as_bytes())).collect();
+    assert_eq!(lines.unwrap(), ["héllo", "wörld", "日本語"]);
+
+    let mut lines = run_reader!(line(), Trickle(b"ab\ncd"));
+    assert_eq!(lines.next(), Some(Ok("ab".to_string())));
+    assert_eq!(lines.next(), Some(Err(("should be char \n but get ".to_string(), Location { line: 2, col: 3 }))));
+    assert_eq!(lines.next(), None);
+
+    let path = std::env::temp_dir().join(format!("reader_test_{}.txt", std::process::id()));
+    std::fs::write(&path, "a\nb\n").unwrap();
+    let lines: Vec<_> = run_file!(line(), &path).unwrap().collect();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(lines, [Ok("a".to_string()), Ok("b".to_string())]);
+    std::fs::write(&path, b"a\n\xff\n").unwrap();
+    let lines: Vec<_> = run_file!(line(), &path).unwrap().collect();
+    assert_eq!(lines, [Ok("a".to_string()), Err(("invalid utf-8".to_string(), Location { line: 2, col: 1 }))]);
+
+    let mapped = |text: &[u8]| {
+        std::fs::write(&path, text).unwrap();
+        // the file does not change while it is read
+        let reader = unsafe { Reader::map(&path) }.unwrap();
+        reader.values(|input, loc| line().run_with_out(input, loc)).collect::<Vec<_>>()
+    };
+    let long = "b".repeat(3 * CHUNK);
+    assert_eq!(mapped(format!("a\n{}\n日本語\n", long).as_bytes()), [Ok("a".to_string()), Ok(long), Ok("日本語".to_string())]);
+    assert_eq!(mapped(b""), []);
+    assert_eq!(mapped(b"a\n\xff\n"), lines);
+    std::fs::remove_file(&path).unwrap();
+
+    // a long value read a few bytes at a time is not parsed again for every read
+    let long = format!("{}\n", "a".repeat(3000));
+    let parses = std::cell::Cell::new(0);
+    let lines: Vec<_> = Reader::new(Trickle(long.as_bytes())).values(|input, loc| {
+        parses.set(parses.get() + 1);
+        line().run_with_out(input, loc)
+    }).collect();
+    assert_eq!(lines, [Ok("a".repeat(3000))]);
+    assert!(parses.get() < 20);
+    assert!(run_reader!(line(), Trickle(b"a\n\xe6\x97")).nth(1).unwrap().is_err());
+}
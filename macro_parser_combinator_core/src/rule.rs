@@ -0,0 +1,91 @@
+use std::cell::OnceCell;
+use std::rc::{Rc, Weak};
+use crate::location::Location;
+use crate::parser::Parser;
+use crate::depth::Depth;
+
+type Dyn<'a, O> = dyn Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + 'a;
+
+/// a parser that is used before it is defined, for recursive grammars.
+/// the parser is built and boxed once by `define`, `rule.parser()` is a parser that calls it,
+/// so a recursive call neither builds nor allocates anything.
+/// the rule only keeps a weak reference, the parser borrows the rule and lives in the `Defined`
+/// that `define` returns, so it must be dropped before the rule
+pub struct Rule<'a, O> {
+    parser: OnceCell<Weak<Dyn<'a, O>>>,
+}
+
+/// the parser of a rule, the rule works while it lives
+pub struct Defined<'a, O>(Rc<Dyn<'a, O>>);
+
+impl<'a, O> Default for Rule<'a, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, O> Rule<'a, O> {
+    pub fn new() -> Self {
+        Rule { parser: OnceCell::new() }
+    }
+    /// a parser that runs the defined parser
+    pub fn parser(&self) -> Parser<impl Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy + '_, &'a str, O> {
+        Parser::new(move |input: &'a str, loc: Location| self.call(input, loc))
+    }
+    /// panics when the rule is defined twice
+    pub fn define<F>(&self, p: Parser<F, &'a str, O>) -> Defined<'a, O>
+    where
+        F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy + 'a
+    {
+        let parser: Rc<Dyn<'a, O>> = Rc::new(p.0);
+        if self.parser.set(Rc::downgrade(&parser)).is_err() {
+            panic!("rule is defined twice");
+        }
+        Defined(parser)
+    }
+    fn call(&self, input: &'a str, loc: Location) -> (Result<O, (String, Location)>, &'a str, Location) {
+        let _depth = match Depth::enter(loc) {
+            Ok(d) => d,
+            Err(e) => return (Err(e), input, loc),
+        };
+        match self.parser.get().map(Weak::upgrade) {
+            Some(Some(p)) => p(input, loc),
+            Some(None) => panic!("rule is used after its definition is dropped"),
+            None => panic!("rule is used before it is defined"),
+        }
+    }
+}
+
+impl<'a, O> Defined<'a, O> {
+    /// a parser that runs the definition
+    pub fn parser(&self) -> Parser<impl Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy + '_, &'a str, O> {
+        Parser::new(move |input: &'a str, loc: Location| (self.0)(input, loc))
+    }
+}
+
+/// define `rule` by a parser that gets the rule itself, and return the definition
+/// ```ignore
+/// let rule = Rule::new();
+/// let list = recursive(&rule, |list| (char!('(') >> list.parser().many() << char!(')')).map(|v| v.len()));
+/// list.parser().run("(()())")
+/// ```
+pub fn recursive<'r, 'a, O, F, G>(rule: &'r Rule<'a, O>, g: G) -> Defined<'a, O>
+where
+    G: FnOnce(&'r Rule<'a, O>) -> Parser<F, &'a str, O>,
+    F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy + 'a
+{
+    rule.define(g(rule))
+}
+
+#[test]
+fn test_recursive() {
+    use crate::char;
+    let rule = Rule::new();
+    let depth = recursive(&rule, |nested| {
+        (char!('(') >> nested.parser().to_try() << char!(')'))
+            .map(|inner: Option<usize>| inner.map_or(1, |d| d + 1))
+    });
+    assert_eq!(depth.parser().run("((()))").unwrap(), 3);
+    assert!(depth.parser().run("(()").is_err());
+    assert_eq!(rule.parser().run("()").unwrap(), 1);
+}
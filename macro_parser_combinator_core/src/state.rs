@@ -0,0 +1,67 @@
+use std::ops::{Deref, DerefMut};
+
+/// the user state of `#[state]` rules. `rollback` takes a checkpoint before a branch
+/// and restores it when the branch fails, so the state is not copied on every branch
+pub trait Checkpoint {
+    type Saved;
+    fn checkpoint(&self) -> Self::Saved;
+    /// undo the changes made since `saved` was taken
+    fn restore(&mut self, saved: Self::Saved);
+}
+
+/// a vec is a log that is only pushed to: the checkpoint is its length and restoring
+/// truncates it. other changes are not undone, wrap it in `Cloned` for them
+impl<T> Checkpoint for Vec<T> {
+    type Saved = usize;
+    fn checkpoint(&self) -> usize {
+        self.len()
+    }
+    fn restore(&mut self, saved: usize) {
+        self.truncate(saved);
+    }
+}
+
+/// a state that is cloned at every checkpoint, for a state that is cheap to clone,
+/// like an `Rc` list, or when there are few branches
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cloned<T>(pub T);
+
+impl<T: Clone> Checkpoint for Cloned<T> {
+    type Saved = T;
+    fn checkpoint(&self) -> T {
+        self.0.clone()
+    }
+    fn restore(&mut self, saved: T) {
+        self.0 = saved;
+    }
+}
+
+impl<T> Deref for Cloned<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Cloned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+#[test]
+fn test_rollback() {
+    use std::cell::RefCell;
+    use crate::{token_base, token, whitespace, Location, Parser};
+    let names = RefCell::new(vec!["a"]);
+    let push = token!("x").map_state(&names, |n, x| n.push(x));
+    assert!((push * token!("y")).rollback(&names).run("x z").is_err());
+    assert_eq!(*names.borrow(), ["a"]);
+    assert!((push * token!("y")).rollback(&names).run("x y").is_ok());
+    assert_eq!(*names.borrow(), ["a", "x"]);
+
+    let count = RefCell::new(Cloned(0));
+    let add = token!("x").map_state(&count, |c, _| **c += 1);
+    assert!((add * token!("y")).rollback(&count).run("x z").is_err());
+    assert_eq!(*count.borrow(), Cloned(0));
+}
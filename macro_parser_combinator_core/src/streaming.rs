@@ -0,0 +1,75 @@
+use std::cell::Cell;
+use crate::location::Location;
+use crate::parser::Parser;
+
+thread_local! {
+    static STREAMING: Cell<bool> = const { Cell::new(false) };
+    /// the first primitive that hit the end of the input in `run_partial`
+    static NEEDED: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// the result of `run_partial`
+#[derive(Debug, PartialEq)]
+pub enum Partial<'a, O> {
+    /// the value, the rest of the input and its location
+    Done(O, &'a str, Location),
+    /// the input ended too early, at least this many more bytes are needed
+    Incomplete(usize),
+}
+
+/// a primitive hit the end of the input, and `needed` more bytes could change its result.
+/// only counts in `run_partial`
+pub fn need(needed: usize) {
+    if STREAMING.with(|s| s.get()) {
+        NEEDED.with(|n| if n.get().is_none() {
+            n.set(Some(needed));
+        });
+    }
+}
+
+/// run in streaming mode, with what the first primitive that hit the end of the input needed
+pub(crate) fn streamed<T>(run: impl FnOnce() -> T) -> (T, Option<usize>) {
+    let outer = (STREAMING.with(|s| s.replace(true)), NEEDED.with(|n| n.take()));
+    let ret = run();
+    let needed = NEEDED.with(|n| n.replace(outer.1));
+    STREAMING.with(|s| s.set(outer.0));
+    (ret, needed)
+}
+
+impl<'a, F, O> Parser<F, &'a str, O>
+where
+    F: Fn(&'a str, Location) -> (Result<O, (String, Location)>, &'a str, Location) + Copy
+{
+    /// run on the input read so far, starting at `loc`.
+    /// when a primitive hits the end of the input it is `Incomplete`, even when the parser
+    /// matched or failed after that, so the caller reads more and runs again
+    pub fn run_partial(&self, input: &'a str, loc: Location) -> Result<Partial<'a, O>, (String, Location)> {
+        let (((ret, rest, rest_loc), needed), exceeded) = crate::depth::run(|| streamed(|| self.0(input, loc)));
+        if let Some(e) = exceeded {
+            return Err(e);
+        }
+        match (needed, ret) {
+            (Some(needed), _) => Ok(Partial::Incomplete(needed)),
+            (None, Ok(o)) => Ok(Partial::Done(o, rest, rest_loc)),
+            (None, Err(e)) => Err(e),
+        }
+    }
+}
+
+#[test]
+fn test_partial() {
+    use crate::{token_base, whitespace, regex, lazy_static, Regex};
+    let stmt = ((token_base!("let") << whitespace!()) >> regex!("[a-z]+")) << token_base!(";");
+    assert_eq!(stmt.run_partial("le", Location::new()), Ok(Partial::Incomplete(1)));
+    assert_eq!(stmt.run_partial("let", Location::new()), Ok(Partial::Incomplete(1)));
+    assert_eq!(stmt.run_partial("let xy", Location::new()), Ok(Partial::Incomplete(1)));
+    let Ok(Partial::Done(name, rest, loc)) = stmt.run_partial("let xy; let", Location::new()) else {
+        panic!("a whole statement");
+    };
+    assert_eq!((name.as_str(), rest, loc.col), ("xy", " let", 8));
+    assert!(stmt.run_partial("var", Location::new()).is_err());
+
+    let items = (regex!("[a-z]+") << token_base!(",")).many();
+    assert_eq!(items.run_partial("a,b,", Location::new()), Ok(Partial::Incomplete(1)));
+    assert!(matches!(items.run_partial("a,b,;", Location::new()), Ok(Partial::Done(v, ";", _)) if v.len() == 2));
+}
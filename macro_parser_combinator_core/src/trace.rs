@@ -0,0 +1,69 @@
+use std::cell::Cell;
+use crate::location::Location;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+#[cfg(test)]
+thread_local! {
+    /// the lines of the thread, instead of stderr
+    static LINES: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+}
+
+fn print(line: String) {
+    #[cfg(test)]
+    if LINES.with_borrow(Option::is_some) {
+        LINES.with_borrow_mut(|lines| lines.as_mut().map(|l| l.push(line)));
+        return;
+    }
+    eprintln!("{}", line);
+}
+
+pub(crate) fn enter(name: &'static str, loc: Location) {
+    let depth = DEPTH.with(|d| d.replace(d.get() + 1));
+    print(format!("{}{} {}:{}", "  ".repeat(depth), name, loc.line, loc.col));
+}
+
+pub(crate) fn exit(name: &'static str, loc: Location, ret: Result<&str, &str>) {
+    let depth = DEPTH.with(|d| {
+        d.set(d.get().saturating_sub(1));
+        d.get()
+    });
+    let indent = "  ".repeat(depth);
+    match ret {
+        Ok(consumed) => {
+            let mut text: String = consumed.chars().take(40).collect();
+            if text.len() < consumed.len() {
+                text.push_str("...");
+            }
+            print(format!("{}{} ok {}:{} {:?}", indent, name, loc.line, loc.col, text))
+        },
+        Err(msg) => print(format!("{}{} err {}:{} {}", indent, name, loc.line, loc.col, msg)),
+    }
+}
+
+#[test]
+fn test_trace() {
+    use crate::{token, token_base, whitespace, regex, lazy_static, Regex, Parser};
+    LINES.set(Some(Vec::new()));
+    let key = regex!("[a-z]+").trace("key");
+    let value = (token!("=") >> regex!("[0-9]+")).trace("value");
+    let pair = (key * value).trace("pair");
+    assert!(pair.run("a=1").is_ok());
+    assert!(pair.run("a 1").is_err());
+    assert_eq!(LINES.take().unwrap(), [
+        "pair 1:1",
+        "  key 1:1",
+        "  key ok 1:2 \"a\"",
+        "  value 1:2",
+        "  value ok 1:4 \"=1\"",
+        "pair ok 1:4 \"a=1\"",
+        "pair 1:1",
+        "  key 1:1",
+        "  key ok 1:2 \"a\"",
+        "  value 1:2",
+        "  value err 1:2 should be token = but get  ",
+        "pair err 1:2 should be token = but get  ",
+    ]);
+}
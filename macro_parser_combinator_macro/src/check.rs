@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use quote::{format_ident, quote, ToTokens};
+use syn::LitStr;
+
+use crate::expr::{Expr, Expr1, Expr2};
+use crate::file::Source;
+use crate::term::Term;
+use crate::Parser;
+
+type TokenStream = quote::__private::TokenStream;
+type Span = quote::__private::Span;
+
+/// grammar mistakes found before the parser runs
+/// 1. an alternative shadowed by an earlier token: `"in" | "int"`
+/// 2. `{}` over an item that matches empty input, or `{a("")}` with an empty separator, it never stops
+/// 3. an invalid regex
+/// 4. rules not used by any `#[entry]` or `pub` rule, as warnings
+///
+/// the rules of a grammar file only have the span of `parser_file!`, so their errors
+/// and warnings name the line and column in `source`
+pub fn check(rules: &[&Parser], source: Option<&Source>) -> TokenStream {
+    let errors = errors(rules).into_iter().map(|(rule, at, msg)| match source {
+        Some(source) => {
+            let msg = source.error_at(rule.rest, &at.to_token_stream().to_string(), msg);
+            syn::Error::new(Span::call_site(), msg)
+        },
+        None => syn::Error::new_spanned(at, msg),
+    }).map(syn::Error::into_compile_error);
+    let warnings = unused(rules).into_iter().map(|rule| {
+        let name = &rule.name;
+        let warning = format_ident!("unused_rule", span = name.span());
+        let note = format!("rule `{}` is not used by any #[entry] or pub rule", name);
+        let note = match source {
+            Some(source) => source.error_at(rule.rest, &name.to_string(), note),
+            None => note,
+        };
+        // a proc macro can not emit a warning on stable, but the use of a deprecated
+        // item is one: the note is the message and the span of the use is the rule
+        quote!(
+            const _: () = {
+                #[deprecated(note = #note)]
+                #[allow(non_upper_case_globals)]
+                const unused_rule: () = ();
+                #warning
+            };
+        )
+    });
+    quote!(#(#errors)* #(#warnings)*)
+}
+
+/// the errors of the rules, with the token they point at
+fn errors<'r>(rules: &[&'r Parser]) -> Vec<(&'r Parser, &'r dyn ToTokens, String)> {
+    let mut errors: Vec<(&Parser, &dyn ToTokens, String)> = Vec::new();
+    let grammar = Grammar::new(rules);
+    for &rule in rules {
+        // a parameter is only known to match empty input where the rule is called
+        let nullable = Scope { params: rule.params.iter().map(|(p, _)| (p.to_string(), false)).collect(), depth: 0 };
+        let nullable = |e: &Expr| e.nullable(&grammar, &nullable);
+        visit(&rule.expr, &mut |e| match e {
+            Node::Expr(e @ Expr::Or(..)) => {
+                errors.extend(shadowed(e).into_iter().map(|(t, msg)| (rule, t as &dyn ToTokens, msg)));
+            },
+            Node::Term(Term::Many(inner)) if nullable(inner) => {
+                errors.push((rule, inner.first(), "`{}` over an item that can match empty input never stops".to_string()));
+            },
+            Node::Term(Term::ManySep(inner, sep)) if nullable(inner) && sep.value().is_empty() => {
+                errors.push((rule, inner.first(), "`{}` over an item that can match empty input, with an empty separator, never stops".to_string()));
+            },
+            Node::Term(Term::Regex(s)) => {
+                if let Err(err) = regex::Regex::new(&s.value()) {
+                    errors.push((rule, s, format!("invalid regex: {}", err)));
+                }
+            },
+            _ => {},
+        });
+    }
+    errors
+}
+
+enum Node<'e> {
+    Expr(&'e Expr),
+    Term(&'e Term),
+}
+
+/// call `f` on every expr and term
+fn visit<'e>(e: &'e Expr, f: &mut dyn FnMut(Node<'e>)) {
+    f(Node::Expr(e));
+    for a in alternatives(e) {
+        for item in a.items() {
+            visit_term(item.term(), f);
+        }
+    }
+}
+
+fn visit_term<'e>(t: &'e Term, f: &mut dyn FnMut(Node<'e>)) {
+    f(Node::Term(t));
+    match t {
+        Term::Paren(e) | Term::Try(e) | Term::Many(e) | Term::ManySep(e, _) | Term::Rollback(e) => visit(e, f),
+        Term::Call(_, args) | Term::StateCall(_, args) => args.iter().for_each(|e| visit(e, f)),
+        _ => {},
+    }
+}
+
+/// the term of an alternative that is only a token
+fn only_token(e: &Expr2) -> Option<&LitStr> {
+    match e {
+        Expr2::Term(Expr1::Term(Term::Token(s)) | Expr1::Map(Term::Token(s), _) | Expr1::Label(Term::Token(s), _)) => Some(s),
+        _ => None,
+    }
+}
+
+/// the token an alternative starts with
+fn first_token(e: &Expr2) -> Option<&LitStr> {
+    let first = match e {
+        Expr2::Action(bindings, _) => &bindings.first()?.expr,
+        Expr2::Chain(first, _) | Expr2::Term(first) => first,
+    };
+    match first.term() {
+        Term::Token(s) => Some(s),
+        Term::Paren(e) => match &**e {
+            Expr::Term(e) => first_token(e),
+            Expr::Or(..) => None,
+        },
+        _ => None,
+    }
+}
+
+fn alternatives(e: &Expr) -> Vec<&Expr2> {
+    match e {
+        Expr::Or(a, b) => std::iter::once(a).chain(alternatives(b)).collect(),
+        Expr::Term(a) => vec![a],
+    }
+}
+
+/// the tokens of later alternatives that an earlier token alternative matches first
+fn shadowed(e: &Expr) -> Vec<(&LitStr, String)> {
+    let mut ret = Vec::new();
+    let alternatives = alternatives(e);
+    for (i, a) in alternatives.iter().enumerate() {
+        let Some(prefix) = only_token(a) else {
+            continue;
+        };
+        for b in &alternatives[i + 1..] {
+            if let Some(t) = first_token(b) {
+                if t.value().starts_with(&prefix.value()) {
+                    ret.push((t, format!(
+                        "this alternative never matches, the token {:?} before it matches first", prefix.value()
+                    )));
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// how deep the calls of rules with parameters are looked into
+const MAX_CALLS: usize = 8;
+
+/// the rules that can match empty input, their parameters taken to be able to
+struct Grammar<'r> {
+    nullable: HashMap<String, bool>,
+    rules: HashMap<String, &'r Parser>,
+}
+
+/// the parameters of the rule being looked into, by whether their argument can match empty input
+struct Scope {
+    params: HashMap<String, bool>,
+    depth: usize,
+}
+
+impl<'r> Grammar<'r> {
+    fn new(rules: &[&'r Parser]) -> Self {
+        let mut grammar = Grammar {
+            nullable: rules.iter().map(|r| (r.name.to_string(), false)).collect(),
+            rules: rules.iter().map(|r| (r.name.to_string(), *r)).collect(),
+        };
+        loop {
+            let mut changed = false;
+            for r in rules {
+                let scope = Scope { params: r.params.iter().map(|(p, _)| (p.to_string(), true)).collect(), depth: 0 };
+                if !grammar.nullable[&r.name.to_string()] && r.expr.nullable(&grammar, &scope) {
+                    grammar.nullable.insert(r.name.to_string(), true);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return grammar;
+            }
+        }
+    }
+}
+
+impl Expr {
+    fn nullable(&self, grammar: &Grammar, scope: &Scope) -> bool {
+        alternatives(self).into_iter().any(|e| e.nullable(grammar, scope))
+    }
+    /// the first token, errors point at it
+    fn first(&self) -> &dyn ToTokens {
+        alternatives(self)[0].first()
+    }
+}
+
+impl Expr2 {
+    fn items(&self) -> Vec<&Expr1> {
+        match self {
+            Expr2::Action(bindings, _) => bindings.iter().map(|b| &b.expr).collect(),
+            Expr2::Chain(first, rest) => std::iter::once(first).chain(rest.iter().map(|(_, e)| e)).collect(),
+            Expr2::Term(e) => vec![e],
+        }
+    }
+    fn nullable(&self, grammar: &Grammar, scope: &Scope) -> bool {
+        self.items().into_iter().all(|e| e.term().nullable(grammar, scope))
+    }
+    fn first(&self) -> &dyn ToTokens {
+        self.items()[0].term().first()
+    }
+}
+
+impl Expr1 {
+    fn term(&self) -> &Term {
+        match self {
+            Expr1::Map(t, _) | Expr1::Flatmap(t, _) | Expr1::Recover(t, _) | Expr1::Label(t, _) | Expr1::Term(t) => t,
+        }
+    }
+}
+
+impl Term {
+    /// can match empty input, parameters, rules and builtins are looked up by name,
+    /// a call of a rule looks into the rule with its arguments, other functions are taken to consume input
+    fn nullable(&self, grammar: &Grammar, scope: &Scope) -> bool {
+        match self {
+            Term::Func(f) | Term::StateFunc(f) | Term::Param(f) => {
+                let name = f.to_string();
+                scope.params.get(&name).or(grammar.nullable.get(&name)).copied()
+                    .unwrap_or(name == "whitespace" || name == "eof")
+            },
+            Term::Regex(s) => regex::Regex::new(&s.value()).is_ok_and(|r| r.is_match("")),
+            Term::Token(s) => s.value().is_empty(),
+            Term::Paren(e) | Term::Rollback(e) => e.nullable(grammar, scope),
+            Term::Try(_) | Term::Many(_) | Term::ManySep(_, _) => true,
+            Term::Call(f, args) | Term::StateCall(f, args) => {
+                let args: Vec<bool> = args.iter().map(|a| a.nullable(grammar, scope)).collect();
+                match (grammar.rules.get(&f.to_string()), f.to_string().as_str()) {
+                    (Some(rule), _) if scope.depth < MAX_CALLS => {
+                        let params = rule.params.iter().map(|(p, _)| p.to_string()).zip(args).collect();
+                        rule.expr.nullable(grammar, &Scope { params, depth: scope.depth + 1 })
+                    },
+                    (Some(rule), _) => grammar.nullable[&rule.name.to_string()],
+                    // the items can be none
+                    (None, "aligned") => true,
+                    // the items are on the lines after the header
+                    (None, "indented_block") => false,
+                    (None, "same_line") => args.iter().all(|a| *a),
+                    (None, _) => false,
+                }
+            },
+            Term::Parser(_) => false,
+        }
+    }
+    fn first(&self) -> &dyn ToTokens {
+        match self {
+            Term::Func(f) | Term::StateFunc(f) | Term::Param(f) | Term::Call(f, _) | Term::StateCall(f, _) => f,
+            Term::Regex(s) | Term::Token(s) => s,
+            Term::Paren(e) | Term::Try(e) | Term::Many(e) | Term::ManySep(e, _) | Term::Rollback(e) => e.first(),
+            Term::Parser(ty) => ty,
+        }
+    }
+}
+
+/// rules that the `#[entry]` and `pub` rules do not use, nothing without them
+fn unused<'r>(rules: &[&'r Parser]) -> Vec<&'r Parser> {
+    let refs: HashMap<String, Vec<String>> = rules.iter().map(|r| {
+        let mut names = Vec::new();
+        visit(&r.expr, &mut |n| {
+            if let Node::Term(Term::Func(f) | Term::Call(f, _)) = n {
+                names.push(f.to_string());
+            }
+        });
+        (r.name.to_string(), names)
+    }).collect();
+    let mut used: Vec<String> = rules.iter().filter(|r| r.entry || r.export).map(|r| r.name.to_string()).collect();
+    if used.is_empty() {
+        return Vec::new();
+    }
+    let mut i = 0;
+    while i < used.len() {
+        for name in refs.get(&used[i]).into_iter().flatten() {
+            if refs.contains_key(name) && !used.contains(name) {
+                used.push(name.clone());
+            }
+        }
+        i += 1;
+    }
+    rules.iter().filter(|r| !used.contains(&r.name.to_string())).copied().collect()
+}
+
+#[cfg(test)]
+fn rules(text: &str) -> crate::Rules {
+    syn::parse_str(text).unwrap()
+}
+
+#[cfg(test)]
+fn messages(text: &str) -> Vec<(String, String)> {
+    let rules = rules(text);
+    errors(&rules.parser.rules()).into_iter().map(|(rule, _, msg)| (rule.name.to_string(), msg)).collect()
+}
+
+#[test]
+fn test_shadowed() {
+    let errors = messages(r#"a: A = "in" | "int" | "x""#);
+    assert_eq!(errors, [("a".to_string(), "this alternative never matches, the token \"in\" before it matches first".to_string())]);
+    assert!(messages(r#"a: A = "int" | "in""#).is_empty());
+}
+
+#[test]
+fn test_nullable() {
+    let never_stops = |text: &str| messages(text).iter().any(|(_, msg)| msg.contains("never stops"));
+    assert!(never_stops(r#"a: A = {["x"]}"#));
+    assert!(never_stops(r#"a: A = {whitespace}"#));
+    assert!(never_stops(r#"a: A = {b} b: B = r"[a-z]*""#));
+    assert!(never_stops(r#"a: A = {b("")} b: B = ["x"]"#));
+    assert!(!never_stops(r#"a: A = {b(",")} b: B = ["x"]"#));
+    // the indent combinators, `{f("x")}` would be `f` with a separator
+    assert!(never_stops(r#"a: A = {(aligned("x"))}"#));
+    assert!(!never_stops(r#"a: A = {indented_block("x", "y")}"#));
+    assert!(never_stops(r#"a: A = {same_line(["x"], whitespace)}"#));
+    assert!(!never_stops(r#"a: A = {same_line(["x"], "y")}"#));
+    // a parameter matches empty input when its argument does
+    assert!(!never_stops(r#"opt(p: P): P = p"#));
+    assert!(never_stops(r#"opt(p: P): P = p a: A = {opt(["x"])}"#));
+    assert!(!never_stops(r#"opt(p: P): P = p a: A = {(opt("x"))}"#));
+    // other functions are taken to consume input
+    assert!(!never_stops(r#"a: A = {(f(""))}"#));
+}
+
+#[test]
+fn test_regex() {
+    let errors = messages(r#"a: A = r"[a-z" | r"[a-z]""#);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].1.starts_with("invalid regex"));
+}
+
+#[test]
+fn test_unused() {
+    let unused_rules = |text: &str| {
+        let rules = rules(text);
+        unused(&rules.parser.rules()).iter().map(|r| r.name.to_string()).collect::<Vec<_>>()
+    };
+    assert_eq!(unused_rules(r#"#[entry] a: A = b b: B = c(d) c(p: P): P = p d: D = "x" e: E = "y" pub f: F = g g: G = "z""#), ["e"]);
+    // nothing without an entry or a pub rule
+    assert!(unused_rules(r#"a: A = "x" b: B = "y""#).is_empty());
+    assert_eq!(unused_rules(r#"pub a: A = "x" b: B = "y""#), ["b"]);
+}
+
+#[test]
+fn test_check_file() {
+    use std::str::FromStr;
+    let text = "item: i64 = int\n\nitems: Ints = {item(\",\")} | {[\"x\"]}\n";
+    let source = Source::new("list.peg".to_string(), text.to_string()).unwrap();
+    let rules: crate::Rules = source.parse(TokenStream::from_str(text).unwrap()).unwrap();
+    let checks = check(&rules.parser.rules(), Some(&source)).to_string();
+    assert!(checks.contains("list.peg:3:31: `{}` over an item that can match empty input never stops"), "{}", checks);
+}
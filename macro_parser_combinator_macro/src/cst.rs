@@ -0,0 +1,101 @@
+use quote::quote;
+
+use crate::expr::{Expr, Expr1, Expr2};
+use crate::term::Term;
+use crate::Parser;
+
+type TokenStream = quote::__private::TokenStream;
+
+/// a rule of `#![cst]`: every item returns its leaves and nodes as `Vec<Cst>`,
+/// the rule wraps them in a node. maps and actions are dropped,
+/// skipped whitespace is kept as trivia of the tokens
+pub fn rule(p: &Parser) -> syn::Result<TokenStream> {
+    if let Some(s) = &p.state {
+        return Err(syn::Error::new_spanned(s, "#[state] is not supported with #![cst]"));
+    }
+    if let Some((name, _)) = p.params.first() {
+        return Err(syn::Error::new(name.span(), "parameters are not supported with #![cst]"));
+    }
+    let name = &p.name;
+    let rule = name.to_string();
+    let label = p.label.as_ref().map(|l| l.value()).unwrap_or_else(|| rule.clone());
+    let body = expr(&p.expr)?;
+    let body = if p.entry {
+        quote!(((#body) << eof!()))
+    } else {
+        body
+    };
+    let vis = if p.export { quote!(pub) } else { quote!(pub(crate)) };
+    Ok(quote!(#vis fn #name<'a>() -> Parser!(Cst) {
+        #body.node(module_path!(), #rule).label(#label).context(#rule).trace(#rule)
+    }))
+}
+
+fn expr(e: &Expr) -> syn::Result<TokenStream> {
+    Ok(match e {
+        Expr::Or(a, b) => {
+            let (a, b) = (expr2(a)?, expr(b)?);
+            quote!((#a) | #b)
+        },
+        Expr::Term(a) => expr2(a)?,
+    })
+}
+
+fn expr2(e: &Expr2) -> syn::Result<TokenStream> {
+    let items: Vec<&Expr1> = match e {
+        Expr2::Action(bindings, _) => bindings.iter().map(|b| &b.expr).collect(),
+        Expr2::Chain(first, rest) => std::iter::once(first).chain(rest.iter().map(|(_, e)| e)).collect(),
+        Expr2::Term(e) => vec![e],
+    };
+    let mut items = items.into_iter().map(expr1);
+    let first = items.next().expect("expr without items")?;
+    items.try_fold(first, |acc, e| {
+        let e = e?;
+        Ok(quote!(((#acc) * (#e)).map(cst::concat)))
+    })
+}
+
+fn expr1(e: &Expr1) -> syn::Result<TokenStream> {
+    match e {
+        Expr1::Map(t, _) | Expr1::Term(t) => term(t),
+        Expr1::Label(t, s) => {
+            let t = term(t)?;
+            Ok(quote!((#t).label(#s)))
+        },
+        Expr1::Flatmap(_, e) => Err(syn::Error::new_spanned(e, "`=>` is not supported with #![cst]")),
+        Expr1::Recover(_, s) => Err(syn::Error::new(s.span(), "`~` is not supported with #![cst]")),
+    }
+}
+
+fn term(t: &Term) -> syn::Result<TokenStream> {
+    Ok(match t {
+        Term::Func(f) => match f.to_string().as_str() {
+            "whitespace" => quote!(#f().trivia()),
+            "eof" => quote!(#f().map(|_| Vec::new())),
+            "int" | "float" | "escaped_quoted" => quote!(#f().leaf()),
+            _ => quote!(tobox!(#f()).map(|node| vec![node])),
+        },
+        Term::Regex(s) => quote!(regex!(#s).leaf()),
+        Term::Token(s) => quote!((token_base!(#s).leaf() * whitespace!().trivia()).map(cst::concat)),
+        Term::Paren(e) => {
+            let e = expr(e)?;
+            quote!((#e))
+        },
+        Term::Try(e) => {
+            let e = expr(e)?;
+            quote!((#e).to_try().map(Option::unwrap_or_default))
+        },
+        Term::Many(e) => {
+            let e = expr(e)?;
+            quote!((#e).many().map(cst::flatten))
+        },
+        Term::ManySep(e, s) => {
+            let e = expr(e)?;
+            quote!(cst::many_sep(#e, #s))
+        },
+        Term::Call(f, _) | Term::Param(f) | Term::StateFunc(f) | Term::StateCall(f, _) => {
+            return Err(syn::Error::new(f.span(), "calls are not supported with #![cst]"));
+        },
+        Term::Rollback(_) | Term::Parser(_) => unreachable!("only made by resolve and derive"),
+    })
+}
@@ -0,0 +1,167 @@
+use quote::{format_ident, quote};
+use syn::{parse_quote, Attribute, Data, DeriveInput, Fields, LitStr, Type};
+
+use crate::expr::{Binding, Expr, Expr1, Expr2, Op};
+use crate::term::Term;
+
+type TokenStream = quote::__private::TokenStream;
+
+/// `#[token]`, `#[regex]`, `#[sep]` and `#[suffix]` of a type, a variant or a field
+#[derive(Default)]
+struct Attrs {
+    token: Option<LitStr>,
+    regex: Option<LitStr>,
+    sep: Option<LitStr>,
+    suffix: Option<LitStr>,
+}
+
+impl Attrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut ret = Attrs::default();
+        for attr in attrs {
+            let slot = if attr.path().is_ident("token") {
+                &mut ret.token
+            } else if attr.path().is_ident("regex") {
+                &mut ret.regex
+            } else if attr.path().is_ident("sep") {
+                &mut ret.sep
+            } else if attr.path().is_ident("suffix") {
+                &mut ret.suffix
+            } else {
+                continue;
+            };
+            *slot = Some(attr.parse_args()?);
+        }
+        Ok(ret)
+    }
+}
+
+fn expr(e: Expr1) -> Expr {
+    Expr::Term(Expr2::Term(e))
+}
+
+/// `(whitespace >> term)`
+fn skip(term: Term) -> Expr1 {
+    let chain = Expr2::Chain(
+        Expr1::Term(Term::Func(format_ident!("whitespace"))),
+        vec![(Op::Right, Expr1::Term(term))],
+    );
+    Expr1::Term(Term::Paren(Box::new(Expr::Term(chain))))
+}
+
+fn unnamed(term: Term) -> Binding {
+    Binding { name: None, expr: skip(term) }
+}
+
+/// the last segment of a path type, and its generic argument
+fn segment(ty: &Type) -> Option<(String, Option<&Type>)> {
+    let Type::Path(p) = ty else {
+        return None;
+    };
+    let last = p.path.segments.last()?;
+    let arg = match &last.arguments {
+        syn::PathArguments::AngleBracketed(a) => a.args.iter().find_map(|a| match a {
+            syn::GenericArgument::Type(t) => Some(t),
+            _ => None,
+        }),
+        _ => None,
+    };
+    Some((last.ident.to_string(), arg))
+}
+
+/// the parser of a field from its type
+/// `i64`: int, `f64`: float, `String`: escaped_quoted, `Box<T>`, `Option<T>`,
+/// `Vec<T>` with `#[sep]`, and `T::parser()` for other types
+fn field_expr(ty: &Type, attrs: &Attrs) -> Expr1 {
+    if let Some(r) = &attrs.regex {
+        return Expr1::Term(Term::Regex(r.clone()));
+    }
+    let inner = |t: &Type| Box::new(expr(skip_expr(field_expr(t, &Attrs::default()))));
+    match segment(ty) {
+        Some((name, None)) if name == "i64" => Expr1::Term(Term::Func(format_ident!("int"))),
+        Some((name, None)) if name == "f64" => Expr1::Term(Term::Func(format_ident!("float"))),
+        Some((name, None)) if name == "String" => Expr1::Term(Term::Func(format_ident!("escaped_quoted"))),
+        Some((name, Some(t))) if name == "Box" => Expr1::Map(Term::Paren(Box::new(expr(field_expr(t, attrs)))), parse_quote!(Box::new)),
+        Some((name, Some(t))) if name == "Option" => Expr1::Term(Term::Try(inner(t))),
+        Some((name, Some(t))) if name == "Vec" => match &attrs.sep {
+            Some(sep) => Expr1::Term(Term::ManySep(inner(t), sep.clone())),
+            None => Expr1::Term(Term::Many(inner(t))),
+        },
+        _ => Expr1::Term(Term::Parser(Box::new(ty.clone()))),
+    }
+}
+
+fn skip_expr(e: Expr1) -> Expr1 {
+    skip(Term::Paren(Box::new(expr(e))))
+}
+
+/// bindings of the tokens and fields, and the value built from them
+fn fields(path: TokenStream, attrs: &Attrs, fields: &Fields) -> syn::Result<Expr2> {
+    let mut bindings = Vec::new();
+    if let Some(t) = &attrs.token {
+        bindings.push(unnamed(Term::Token(t.clone())));
+    }
+    if let Some(r) = &attrs.regex {
+        bindings.push(unnamed(Term::Regex(r.clone())));
+    }
+    let mut names = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let field_attrs = Attrs::parse(&field.attrs)?;
+        if let Some(t) = &field_attrs.token {
+            bindings.push(unnamed(Term::Token(t.clone())));
+        }
+        let name = field.ident.clone().unwrap_or_else(|| format_ident!("f{}", i));
+        let e = skip_expr(field_expr(&field.ty, &field_attrs));
+        bindings.push(Binding { name: Some(name.clone()), expr: e });
+        names.push(name);
+        if let Some(s) = &field_attrs.suffix {
+            bindings.push(unnamed(Term::Token(s.clone())));
+        }
+    }
+    if let Some(s) = &attrs.suffix {
+        bindings.push(unnamed(Term::Token(s.clone())));
+    }
+    if bindings.is_empty() {
+        return Err(syn::Error::new_spanned(path, "nothing to parse, add #[token] or #[regex]"));
+    }
+    let value = match fields {
+        Fields::Named(_) => quote!(#path { #(#names),* }),
+        Fields::Unnamed(_) => quote!(#path(#(#names),*)),
+        Fields::Unit => path,
+    };
+    Ok(Expr2::Action(bindings, parse_quote!({ #value })))
+}
+
+fn derive(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let attrs = Attrs::parse(&input.attrs)?;
+    let expr = match &input.data {
+        Data::Struct(s) => Expr::Term(fields(quote!(Self), &attrs, &s.fields)?),
+        Data::Enum(e) => {
+            let mut alternatives = Vec::new();
+            for v in &e.variants {
+                let name = &v.ident;
+                alternatives.push(fields(quote!(Self::#name), &Attrs::parse(&v.attrs)?, &v.fields)?);
+            }
+            let last = match alternatives.pop() {
+                Some(a) => Expr::Term(a),
+                None => return Err(syn::Error::new_spanned(&input.ident, "enum without variants")),
+            };
+            alternatives.into_iter().rev().fold(last, |acc, a| Expr::Or(a, Box::new(acc)))
+        },
+        Data::Union(_) => return Err(syn::Error::new_spanned(&input.ident, "unions are not supported")),
+    };
+    let name = &input.ident;
+    let rule = name.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote!(
+        impl #impl_generics #name #ty_generics #where_clause {
+            pub fn parser<'a>() -> Parser!(Self) {
+                (#expr).label(#rule).context(#rule).trace(#rule)
+            }
+        }
+    ))
+}
+
+pub fn expand(input: &DeriveInput) -> TokenStream {
+    derive(input).unwrap_or_else(syn::Error::into_compile_error)
+}
@@ -1,32 +1,44 @@
-use quote::{ToTokens, quote};
-use syn::{parse::Parse, Token, parenthesized, token::Eq};
+use quote::{ToTokens, quote, format_ident};
+use syn::{parse::{discouraged::Speculative, Parse, ParseStream}, Token, parenthesized, token::{Eq, Paren, Brace}, LitStr, Ident, Block};
 
-use crate::term::Term;
+use crate::file;
+use crate::term::{Term, Sep};
 
 /// first level of expr
 /// 1. term -> (closure)
 /// 2. term => (closure)
-/// 3. term
+/// 3. term ~ (";")
+/// 4. term @ "label"
+/// 5. term
 pub enum Expr1 {
     Map(Term, syn::Expr),
     Flatmap(Term, syn::Expr),
+    Recover(Term, LitStr),
+    Label(Term, LitStr),
     Term(Term),
 }
 
 impl Parse for Expr1 {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let term: Term = input.parse()?;
-        if let Ok(_) = input.parse::<Token![->]>() {
+        if is_map(input) {
+            input.parse::<Token![->]>()?;
             let content;
             let _ = parenthesized!(content in input);
-            let expr = content.parse::<syn::Expr>()?;
+            let expr = file::scope(&content, file::rest(input) + 1, syn::Expr::parse)?;
             Ok(Expr1::Map(term, expr))
         } else if let Ok(_) = input.parse::<Eq>() {
             input.parse::<Token![>]>()?;
             let content;
             let _ = parenthesized!(content in input);
-            let expr = content.parse::<syn::Expr>()?;
+            let expr = file::scope(&content, file::rest(input) + 1, syn::Expr::parse)?;
             Ok(Expr1::Flatmap(term, expr))
+        } else if let Ok(_) = input.parse::<Token![~]>() {
+            let sync: Sep = input.parse()?;
+            Ok(Expr1::Recover(term, sync.sep))
+        } else if let Ok(_) = input.parse::<Token![@]>() {
+            let label: LitStr = input.parse()?;
+            Ok(Expr1::Label(term, label))
         }else {
             Ok(Expr1::Term(term))
         }
@@ -42,37 +54,157 @@ impl ToTokens for Expr1 {
             Expr1::Flatmap(t, e) => {
                 quote!((#t).and_then(#e))
             },
+            Expr1::Recover(t, s) => {
+                quote!((#t).recover(sep!(#s)))
+            },
+            Expr1::Label(t, s) => {
+                quote!((#t).label(#s))
+            },
             Expr1::Term(t) => quote!(#t),
         });
     }
 }
 
+/// `-> (closure)`, not the `-> {block}` of an action
+fn is_map(input: ParseStream) -> bool {
+    let fork = input.fork();
+    fork.parse::<Token![->]>().is_ok() && fork.peek(Paren)
+}
+
+/// a `-> {block}` before the next `|` or the next rule
+fn has_action(input: ParseStream) -> bool {
+    let fork = input.fork();
+    while !fork.is_empty() {
+        if fork.peek(Token![|]) || (fork.peek(Token![=]) && !fork.peek(Token![=>])) {
+            return false;
+        }
+        if fork.parse::<Token![->]>().is_ok() {
+            if fork.peek(Brace) {
+                return true;
+            }
+            continue;
+        }
+        let next = fork.step(|cursor| match cursor.token_tree() {
+            Some((_, rest)) => Ok(((), rest)),
+            None => Err(cursor.error("end of input")),
+        });
+        if next.is_err() {
+            return false;
+        }
+    }
+    false
+}
+
+/// `name:term`, or a bare term whose value is dropped
+pub struct Binding {
+    pub name: Option<Ident>,
+    pub expr: Expr1,
+}
+
+/// the `name:` of a binding, if there is one
+pub(crate) fn binding_name(input: ParseStream) -> syn::Result<Option<Ident>> {
+    if input.peek(Ident) && input.peek2(Token![:]) && !input.peek2(Token![::]) {
+        let name: Ident = input.parse()?;
+        input.parse::<Token![:]>()?;
+        Ok(Some(name))
+    } else {
+        Ok(None)
+    }
+}
+
+impl Parse for Binding {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = binding_name(input)?;
+        let expr: Expr1 = input.parse()?;
+        Ok(Binding { name, expr })
+    }
+}
+
+/// a sequence of bindings and the action block that uses them
+fn parse_action(input: ParseStream) -> syn::Result<Expr2> {
+    let mut bindings = Vec::new();
+    while !input.peek(Token![->]) {
+        bindings.push(input.parse::<Binding>()?);
+    }
+    if bindings.is_empty() {
+        return Err(input.error("expected bindings before the action"));
+    }
+    input.parse::<Token![->]>()?;
+    let block: Block = input.parse()?;
+    Ok(Expr2::Action(bindings, block))
+}
+
+/// operators of a chain
+pub enum Op {
+    Product,
+    Left,
+    Right,
+}
+
+impl Op {
+    fn parse(input: ParseStream) -> Option<Self> {
+        if input.parse::<Token![*]>().is_ok() {
+            Some(Op::Product)
+        } else if input.parse::<Token![<<]>().is_ok() {
+            Some(Op::Left)
+        } else if input.parse::<Token![>>]>().is_ok() {
+            Some(Op::Right)
+        } else {
+            None
+        }
+    }
+}
+
 /// second level of expr
-/// 1. term * expr
-/// 2. term << expr
-/// 3. term >> expr
-/// 4. term
+/// 1. name:term term ... -> {block}
+/// 2. a >> b * c * d << e: a chain of one precedence, the items before `>>` and
+///    after `<<` are dropped, the items joined by `*` return a flat tuple `(b, c, d)`.
+///    so the operators must come in the order `>>`, `*`, `<<`
+/// 3. term
 pub enum Expr2 {
-    Product(Expr1, Box<Expr>),
-    Left(Expr1, Box<Expr>),
-    Right(Expr1, Box<Expr>),
+    Action(Vec<Binding>, Block),
+    Chain(Expr1, Vec<(Op, Expr1)>),
     Term(Expr1),
 }
 
 impl Parse for Expr2 {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        if has_action(input) {
+            let fork = input.fork();
+            if let Ok(action) = parse_action(&fork) {
+                input.advance_to(&fork);
+                return Ok(action);
+            }
+        }
         let first: Expr1 = input.parse()?;
-        if let Ok(_) = input.parse::<Token![*]>() {
-            let second: Expr = input.parse()?;
-            Ok(Expr2::Product(first, Box::new(second)))
-        }else if let Ok(_) = input.parse::<Token![<<]>() {
-            let second: Expr = input.parse()?;
-            Ok(Expr2::Left(first, Box::new(second)))
-        }else if let Ok(_) = input.parse::<Token![>>]>() {
-            let second: Expr = input.parse()?;
-            Ok(Expr2::Right(first, Box::new(second)))
-        }else {
+        let mut rest = Vec::new();
+        let mut last: Option<&'static str> = None;
+        loop {
+            let span = input.span();
+            let op = match Op::parse(input) {
+                Some(op) => op,
+                None => break,
+            };
+            let name = match op {
+                Op::Product => "*",
+                Op::Left => "<<",
+                Op::Right => ">>",
+            };
+            match (last, &op) {
+                (Some("<<"), Op::Product | Op::Right) | (Some("*"), Op::Right) => {
+                    return Err(syn::Error::new(span, format!(
+                        "`{}` after `{}` is ambiguous, add parentheses", name, last.unwrap_or_default()
+                    )));
+                },
+                _ => {},
+            }
+            last = Some(name);
+            rest.push((op, input.parse::<Expr1>()?));
+        }
+        if rest.is_empty() {
             Ok(Expr2::Term(first))
+        } else {
+            Ok(Expr2::Chain(first, rest))
         }
     }
 }
@@ -80,9 +212,45 @@ impl Parse for Expr2 {
 impl ToTokens for Expr2 {
     fn to_tokens(&self, tokens: &mut quote::__private::TokenStream) {
         tokens.extend(match self {
-            Expr2::Product(l, r) => quote!(#l * #r),
-            Expr2::Left(l, r) => quote!(#l << #r),
-            Expr2::Right(l, r) => quote!(#l >> #r),
+            Expr2::Action(bindings, block) => {
+                let mut items = bindings.iter();
+                let first = items.next().expect("action without bindings");
+                let name = |b: &Binding| match &b.name {
+                    Some(n) => quote!(#n),
+                    None => quote!(_),
+                };
+                let (parser, pattern) = items.fold(
+                    (first.expr.to_token_stream(), name(first)),
+                    |(parser, pattern), b| {
+                        let expr = &b.expr;
+                        let n = name(b);
+                        (quote!((#parser * #expr)), quote!((#pattern, #n)))
+                    },
+                );
+                quote!(#parser.map(move |#pattern| #block))
+            },
+            Expr2::Chain(first, rest) => {
+                let mut parser = quote!((#first));
+                let mut kept = 1usize;
+                for (op, e) in rest {
+                    parser = match op {
+                        Op::Product => {
+                            kept += 1;
+                            quote!((#parser * (#e)))
+                        },
+                        Op::Left => quote!((#parser << (#e))),
+                        Op::Right => quote!((#parser >> (#e))),
+                    };
+                }
+                if kept > 2 {
+                    let vars: Vec<_> = (0..kept).map(|i| format_ident!("v{}", i)).collect();
+                    let first = &vars[0];
+                    let pattern = vars[1..].iter().fold(quote!(#first), |p, v| quote!((#p, #v)));
+                    quote!(#parser.map(|#pattern| (#(#vars),*)))
+                } else {
+                    parser
+                }
+            },
             Expr2::Term(t) => quote!(#t),
         });
     }
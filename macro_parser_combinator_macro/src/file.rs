@@ -0,0 +1,291 @@
+use std::cell::{Cell, RefCell};
+use proc_macro2::{TokenStream, TokenTree};
+use syn::buffer::Cursor;
+use syn::parse::{Parse, ParseStream, Parser as _};
+
+thread_local! {
+    /// the tokens of the file after the group being parsed
+    static AFTER: Cell<usize> = const { Cell::new(0) };
+    /// the innermost error of the file and the tokens after it
+    static ERROR: RefCell<Option<(String, usize)>> = const { RefCell::new(None) };
+}
+
+/// a grammar file and where its tokens start.
+/// the tokens of the compiler only know the call site of `parser_file!`,
+/// so the file is scanned again to find the line and column of a token
+pub struct Source {
+    pub path: String,
+    pub text: String,
+    /// offsets of the tokens in the order of the token stream, a group is its open and close bracket around its tokens
+    tokens: Vec<usize>,
+}
+
+impl Source {
+    pub fn new(path: String, text: String) -> Result<Self, String> {
+        let tokens = scan(&text).map_err(|(at, msg)| format!("{}:{}: {}", path, position(&text, at), msg))?;
+        Ok(Source { path, text, tokens })
+    }
+    /// parses the tokens of the file, an error is `path:line:col: msg` at the innermost token it stopped at
+    pub fn parse<T: Parse>(&self, tokens: TokenStream) -> Result<T, String> {
+        ERROR.take();
+        let ret = (|input: ParseStream| scope(input, 0, T::parse)).parse2(tokens);
+        let error = ERROR.take();
+        ret.map_err(|e| {
+            let rest = error.filter(|(msg, _)| *msg == e.to_string()).map_or(0, |(_, rest)| rest);
+            self.error(rest, e)
+        })
+    }
+    /// offset of the token that has `rest` tokens after it
+    fn start(&self, rest: usize) -> usize {
+        self.tokens.len().checked_sub(rest).and_then(|i| self.tokens.get(i)).copied().unwrap_or(self.text.len())
+    }
+    /// `path:line:col: msg` at the token that has `rest` tokens after it
+    pub fn error(&self, rest: usize, msg: impl std::fmt::Display) -> String {
+        format!("{}:{}: {}", self.path, position(&self.text, self.start(rest)), msg)
+    }
+    /// `path:line:col: msg` at the first `token` from the token that has `rest` after it
+    pub fn error_at(&self, rest: usize, token: &str, msg: impl std::fmt::Display) -> String {
+        let start = self.start(rest);
+        let ident = |c: char| c == '_' || c.is_alphanumeric();
+        let at = self.tokens.iter().copied().filter(|at| *at >= start).find(|at| {
+            let text = &self.text[*at..];
+            text.starts_with(token) && !(token.ends_with(ident) && text[token.len()..].starts_with(ident))
+        });
+        format!("{}:{}: {}", self.path, position(&self.text, at.unwrap_or(start)), msg)
+    }
+}
+
+/// the tokens from the cursor of `input` to the end of the file
+pub fn rest(input: ParseStream) -> usize {
+    count(input.cursor()) + AFTER.get()
+}
+
+/// parses the tokens of a group that has `after` tokens of the file after it.
+/// syn only knows the span of the error, the call site, so this keeps the token it stopped at
+pub fn scope<T>(input: ParseStream, after: usize, parse: impl FnOnce(ParseStream) -> syn::Result<T>) -> syn::Result<T> {
+    let outer = AFTER.replace(after);
+    let len = count(input.cursor());
+    let ret = parse(input);
+    if let Err(e) = &ret {
+        let msg = e.to_string();
+        let rest = count(input.cursor()) + after;
+        ERROR.with_borrow_mut(|error| match error {
+            // an error of a group inside this one
+            Some((m, r)) if *m == msg && (after..=after + len).contains(r) => {},
+            _ => *error = Some((msg, rest)),
+        });
+    }
+    AFTER.set(outer);
+    ret
+}
+
+/// the tokens from `cursor` to the end of its group, with the brackets of the groups in it
+fn count(mut cursor: Cursor) -> usize {
+    let mut n = 0;
+    while let Some((tree, next)) = cursor.token_tree() {
+        n += flat(&tree);
+        cursor = next;
+    }
+    n
+}
+
+fn flat(tree: &TokenTree) -> usize {
+    match tree {
+        TokenTree::Group(g) => 2 + g.stream().into_iter().map(|t| flat(&t)).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+/// `line:col` of a byte offset, both from 1, the column in chars
+fn position(text: &str, at: usize) -> String {
+    let before = &text[..at];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+    format!("{}:{}", line, col)
+}
+
+/// the start of every token as the compiler splits them, with the close brackets of groups.
+/// a doc comment is the tokens of its attribute, a lifetime is a `'` and an ident
+fn scan(text: &str) -> Result<Vec<usize>, (usize, &'static str)> {
+    let bytes = text.as_bytes();
+    let at = |i: usize| bytes.get(i).copied().unwrap_or(0);
+    let is_ident = |c: u8| c == b'_' || c.is_ascii_alphanumeric();
+    let mut tokens = Vec::new();
+    let mut open = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i];
+        let ch = text[i..].chars().next().unwrap_or_default();
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c == b'/' && at(i + 1) == b'/' {
+            let end = text[i..].find('\n').map_or(text.len(), |n| i + n);
+            let doc = &text[i..end];
+            if doc.starts_with("//!") {
+                // `# ! [doc = "..."]`
+                tokens.extend([start; 7]);
+            } else if doc.starts_with("///") && !doc.starts_with("////") {
+                // `# [doc = "..."]`
+                tokens.extend([start; 6]);
+            }
+            i = end;
+        } else if c == b'/' && at(i + 1) == b'*' {
+            let doc = &text[i..];
+            if doc.starts_with("/*!") {
+                tokens.extend([start; 7]);
+            } else if doc.starts_with("/**") && !doc.starts_with("/***") && !doc.starts_with("/**/") {
+                tokens.extend([start; 6]);
+            }
+            let mut nested = 0;
+            loop {
+                match (at(i), at(i + 1)) {
+                    (0, _) if i >= bytes.len() => return Err((start, "unterminated block comment")),
+                    (b'/', b'*') => (nested, i) = (nested + 1, i + 2),
+                    (b'*', b'/') => (nested, i) = (nested - 1, i + 2),
+                    _ => i += 1,
+                }
+                if nested == 0 {
+                    break;
+                }
+            }
+        } else if matches!(c, b'(' | b'[' | b'{') {
+            tokens.push(start);
+            open.push((start, c));
+            i += 1;
+        } else if matches!(c, b')' | b']' | b'}') {
+            match open.pop() {
+                Some((_, o)) if matches!((o, c), (b'(', b')') | (b'[', b']') | (b'{', b'}')) => {
+                    tokens.push(start);
+                    i += 1;
+                },
+                _ => return Err((start, "unmatched delimiter")),
+            }
+        } else if let Some(end) = string(text, i) {
+            tokens.push(start);
+            i = end.ok_or((start, "unterminated string"))?;
+            while is_ident(at(i)) {
+                i += 1;
+            }
+        } else if c == b'\'' || (c == b'b' && at(i + 1) == b'\'') {
+            tokens.push(start);
+            if c == b'b' {
+                i += 1;
+            }
+            let len = text[i + 1..].chars().next().map_or(0, char::len_utf8);
+            if at(i + 1) == b'\\' {
+                // the escaped char can be a quote: '\''
+                i = text[i + 3..].find('\'').map(|n| i + n + 4).ok_or((start, "unterminated char"))?;
+            } else if len > 0 && at(i + 1 + len) == b'\'' {
+                i += len + 2;
+            } else {
+                // a lifetime
+                i += 1;
+            }
+        } else if c.is_ascii_digit() {
+            tokens.push(start);
+            i += 1;
+            while is_ident(at(i))
+                || (at(i) == b'.' && at(i + 1).is_ascii_digit())
+                || (matches!(at(i), b'+' | b'-') && matches!(at(i - 1), b'e' | b'E') && !text[start..].starts_with("0x"))
+            {
+                i += 1;
+            }
+        } else if ch == '_' || ch.is_alphabetic() {
+            tokens.push(start);
+            if c == b'r' && at(i + 1) == b'#' {
+                i += 2;
+            }
+            i += text[i..].find(|c: char| !(c == '_' || c.is_alphanumeric())).unwrap_or(text.len() - i);
+        } else {
+            tokens.push(start);
+            i += ch.len_utf8();
+        }
+    }
+    match open.pop() {
+        Some((start, _)) => Err((start, "unclosed delimiter")),
+        None => Ok(tokens),
+    }
+}
+
+/// the end of a string literal at `i`, with its prefix: "", r#""#, b"", br"", c""
+fn string(text: &str, i: usize) -> Option<Option<usize>> {
+    let s = &text[i..];
+    let s = s.strip_prefix(['b', 'c']).unwrap_or(s);
+    let prefix = text.len() - i - s.len();
+    if let Some(raw) = s.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let body = raw[hashes..].strip_prefix('"')?;
+        let close = format!("\"{}", "#".repeat(hashes));
+        let start = i + prefix + 1 + hashes + 1;
+        return Some(body.find(&close).map(|n| start + n + close.len()));
+    }
+    let body = s.strip_prefix('"')?;
+    let start = i + prefix + 1;
+    let mut escaped = false;
+    for (n, c) in body.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(Some(start + n + 1)),
+            _ => {},
+        }
+    }
+    Some(None)
+}
+
+#[cfg(test)]
+fn source(text: &str) -> Source {
+    Source::new("list.peg".to_string(), text.to_string()).unwrap()
+}
+
+#[cfg(test)]
+fn parse(text: &str) -> Result<(), String> {
+    use std::str::FromStr;
+    let source = source(text);
+    source.parse::<crate::Rules>(TokenStream::from_str(text).unwrap()).map(|_| ())
+}
+
+#[test]
+fn test_scan() {
+    assert_eq!(scan(r"'a' '\'' '\\' b'x' '日'"), Ok(vec![0, 4, 9, 14, 19]));
+    // a lifetime is a `'` and an ident
+    assert_eq!(scan("'a x"), Ok(vec![0, 1, 3]));
+    assert_eq!(scan(r###"r#"a"b"# br"c" x"###), Ok(vec![0, 9, 15]));
+    assert_eq!(scan("(a [b {}])"), Ok(vec![0, 1, 3, 4, 6, 7, 8, 9]));
+    assert_eq!(scan("/// d\nx //! e\n// f\ny"), Ok(vec![0, 0, 0, 0, 0, 0, 6, 8, 8, 8, 8, 8, 8, 8, 19]));
+    assert_eq!(scan("/** d */ x"), Ok(vec![0, 0, 0, 0, 0, 0, 9]));
+    assert_eq!(scan("(a]"), Err((2, "unmatched delimiter")));
+    assert_eq!(scan("x '\\'"), Err((2, "unterminated char")));
+}
+
+#[test]
+fn test_scan_tokens() {
+    use std::str::FromStr;
+    // as many tokens as the compiler makes
+    let text = "/// doc\na: A = (\"x\" * [b('\\'')]) | r#\"y\"# -> (|x| x.len())\n//! inner\nb<'a>: B = {c(\",\")}";
+    let tokens: usize = TokenStream::from_str(text).unwrap().into_iter().map(|t| flat(&t)).sum();
+    assert_eq!(scan(text).map(|t| t.len()), Ok(tokens));
+}
+
+#[test]
+fn test_position() {
+    assert_eq!(position("ab\n日本x", 9), "2:3");
+    assert_eq!(position("ab", 0), "1:1");
+    assert_eq!(position("ab\n", 3), "2:1");
+}
+
+#[test]
+fn test_parse_error() {
+    let text = "// rules\nitem: i64 = whitespace >> int\nitems: Ints = \"[\" >> {item(\",\")} << \"]\"\n";
+    assert_eq!(parse(text), Ok(()));
+    // the error is at the token it stopped at, inside the groups
+    let text = "item: i64 = whitespace >> int\nitems: Ints = (\"[\" >> [item *]) << \"]\"\n";
+    assert_eq!(parse(text), Err("list.peg:2:30: unexpected end of input, expected literal".to_string()));
+    let text = "item: i64 = int\nitems: Ints = {item(\",\") * (\"a\" | )}";
+    assert_eq!(parse(text), Err("list.peg:2:35: unexpected end of input, expected literal".to_string()));
+    let text = "item: i64 = int\nitems: Ints = x(\"a\", *)";
+    assert_eq!(parse(text), Err("list.peg:2:22: expected literal".to_string()));
+    let text = "item: i64 = int\n\nitems: Ints = *";
+    assert_eq!(parse(text), Err("list.peg:3:15: expected literal".to_string()));
+}
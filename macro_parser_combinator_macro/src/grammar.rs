@@ -0,0 +1,304 @@
+use quote::{format_ident, quote, ToTokens};
+use syn::{parse::{Parse, ParseStream}, parse_quote, token::Paren, Attribute, Ident, Token, parenthesized, bracketed, braced};
+
+use crate::expr::{binding_name, Binding, Expr, Expr1, Expr2, Op};
+use crate::term::{Sep, Term};
+
+type TokenStream = quote::__private::TokenStream;
+type Span = quote::__private::Span;
+
+/// item of a grammar rule, lowered to the terms of `parser!` with the type of its value
+/// 1. token: "null"
+/// 2. regex: r"[a-z]+"
+/// 3. builtin: int, float, escaped_quoted
+/// 4. rule: Value
+/// 5. try: [":" Type]
+/// 6. many: {Value}, {Value(",")}
+///
+/// `boxed` boxes rules, so that recursive types have a size
+struct Item {
+    expr: Expr1,
+    ty: TokenStream,
+    token: bool,
+}
+
+impl Item {
+    fn parse(input: ParseStream, boxed: bool) -> syn::Result<Self> {
+        if input.peek(syn::token::Bracket) {
+            let content;
+            let bracket = bracketed!(content in input);
+            let (expr, ty) = nested(items(&content, true)?, bracket.span.join())?;
+            Ok(Item { expr: Expr1::Term(Term::Try(Box::new(expr))), ty: quote!(Option<#ty>), token: false })
+        } else if input.peek(syn::token::Brace) {
+            let content;
+            let brace = braced!(content in input);
+            let (expr, ty) = nested(items(&content, false)?, brace.span.join())?;
+            let term = if content.is_empty() {
+                Term::Many(Box::new(expr))
+            } else {
+                Term::ManySep(Box::new(expr), content.parse::<Sep>()?.sep)
+            };
+            Ok(Item { expr: Expr1::Term(term), ty: quote!(Vec<#ty>), token: false })
+        } else {
+            // `Value(",")` in `{}` is a rule and its separator, not a call
+            let span = input.span();
+            let term = if input.peek(Ident) {
+                Term::Func(input.parse()?)
+            } else {
+                input.parse()?
+            };
+            leaf(term, boxed, span)
+        }
+    }
+}
+
+/// a token, a regex, a builtin or a rule, other terms are an error at `span`
+fn leaf(term: Term, boxed: bool, span: Span) -> syn::Result<Item> {
+    let (expr, ty, token) = match term {
+        Term::Token(s) => (Expr1::Map(Term::Token(s), parse_quote!(|x: &str| x.to_string())), quote!(String), true),
+        Term::Regex(s) => (Expr1::Term(Term::Regex(s)), quote!(String), false),
+        Term::Func(r) if r.to_string().starts_with(|c: char| c.is_uppercase()) => {
+            let f = Term::Func(rule_fn(&r));
+            if boxed {
+                (Expr1::Map(f, parse_quote!(Box::new)), quote!(Box<#r>), false)
+            } else {
+                (Expr1::Term(f), quote!(#r), false)
+            }
+        },
+        Term::Func(f) => {
+            let ty = match f.to_string().as_str() {
+                "int" => quote!(i64),
+                "float" => quote!(f64),
+                "escaped_quoted" => quote!(String),
+                _ => return Err(syn::Error::new(f.span(), "unknown builtin, rules start with an uppercase letter")),
+            };
+            (Expr1::Term(Term::Func(f)), ty, false)
+        },
+        _ => return Err(syn::Error::new(span, "expected an item")),
+    };
+    Ok(Item { expr: skip(expr), ty, token })
+}
+
+/// `(whitespace >> expr << whitespace)`
+fn skip(expr: Expr1) -> Expr1 {
+    let whitespace = || Expr1::Term(Term::Func(format_ident!("whitespace")));
+    let chain = Expr2::Chain(whitespace(), vec![(Op::Right, expr), (Op::Left, whitespace())]);
+    Expr1::Term(Term::Paren(Box::new(Expr::Term(chain))))
+}
+
+/// items in `[]`, `{}` or `Variant()`, up to a separator
+fn items(input: ParseStream, boxed: bool) -> syn::Result<Vec<Item>> {
+    let mut ret = Vec::new();
+    while !input.is_empty() && !input.peek(Paren) {
+        ret.push(Item::parse(input, boxed)?);
+    }
+    Ok(ret)
+}
+
+/// `[]` and `{}` hold one value, the tokens around it are dropped, errors point at `span` of the group
+fn nested(items: Vec<Item>, span: Span) -> syn::Result<(Expr, TokenStream)> {
+    let values: Vec<usize> = (0..items.len()).filter(|&i| !items[i].token).collect();
+    let value = match values.as_slice() {
+        [] if items.is_empty() => return Err(syn::Error::new(span, "empty `[]` or `{}`")),
+        [] => items.len() - 1,
+        [v] => *v,
+        [_, _, ..] => return Err(syn::Error::new(span, "only one value is allowed in `[]` and `{}`, make it a rule")),
+    };
+    let ty = items[value].ty.clone();
+    let mut items = items.into_iter().map(|i| i.expr);
+    let first = items.next().expect("items are not empty");
+    let rest: Vec<(Op, Expr1)> = items.enumerate()
+        .map(|(i, e)| (if i < value { Op::Right } else { Op::Left }, e))
+        .collect();
+    let expr = if rest.is_empty() {
+        Expr2::Term(first)
+    } else {
+        Expr2::Chain(first, rest)
+    };
+    Ok((Expr::Term(expr), ty))
+}
+
+/// bindings up to the next `|`, or the next rule, with the types of their values
+fn bindings(input: ParseStream) -> syn::Result<Vec<(Binding, TokenStream)>> {
+    let mut ret = Vec::new();
+    while !input.is_empty()
+        && !input.peek(Token![|])
+        && !input.peek(Token![#])
+        && !(input.peek(Ident) && input.peek2(Token![=]))
+    {
+        let name = binding_name(input)?;
+        let item = Item::parse(input, true)?;
+        ret.push((Binding { name, expr: item.expr }, item.ty));
+    }
+    Ok(ret)
+}
+
+/// a sequence of bindings: the fields, and the parser that returns them
+struct Fields(Vec<(Binding, TokenStream)>);
+
+impl Fields {
+    fn named(&self) -> impl Iterator<Item = (&Ident, &TokenStream)> {
+        self.0.iter().filter_map(|(b, ty)| b.name.as_ref().map(|n| (n, ty)))
+    }
+    /// `{ name: Type, ... }`, or nothing without fields
+    fn decl(&self, public: bool) -> TokenStream {
+        let vis = if public { quote!(pub) } else { quote!() };
+        let fields: Vec<_> = self.named().map(|(n, ty)| quote!(#vis #n: #ty)).collect();
+        if fields.is_empty() {
+            quote!()
+        } else {
+            quote!({ #(#fields),* })
+        }
+    }
+    /// the action of the sequence, it returns `path { fields }`, an empty sequence is an error at `span` of its name
+    fn parser(self, path: TokenStream, span: Span) -> syn::Result<Expr2> {
+        if self.0.is_empty() {
+            return Err(syn::Error::new(span, "empty rule"));
+        }
+        let names: Vec<&Ident> = self.named().map(|(n, _)| n).collect();
+        let value = if names.is_empty() {
+            path
+        } else {
+            quote!(#path { #(#names),* })
+        };
+        let block = parse_quote!({ #value });
+        Ok(Expr2::Action(self.0.into_iter().map(|(b, _)| b).collect(), block))
+    }
+}
+
+/// alternative of an enum rule
+/// 1. Rule: the variant `Rule(Rule)`
+/// 2. Variant(name:item item ...): a variant with the named fields
+enum Alternative {
+    Rule(Ident),
+    Variant(Ident, Fields),
+}
+
+impl Parse for Alternative {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        if input.peek(Paren) {
+            let content;
+            let _ = parenthesized!(content in input);
+            let fields = bindings(&content)?;
+            if !content.is_empty() {
+                return Err(content.error("expected item"));
+            }
+            Ok(Alternative::Variant(name, Fields(fields)))
+        } else {
+            Ok(Alternative::Rule(name))
+        }
+    }
+}
+
+/// body of a grammar rule
+/// 1. a | b | c: an enum
+/// 2. name:item item ...: a struct
+enum Body {
+    Enum(Vec<Alternative>),
+    Struct(Fields),
+}
+
+/// `Name = body`, with the attributes of the type
+struct Rule {
+    attrs: Vec<Attribute>,
+    name: Ident,
+    body: Body,
+}
+
+impl Parse for Rule {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let name: Ident = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let fork = input.fork();
+        let is_enum = fork.parse::<Alternative>().is_ok() && fork.peek(Token![|]);
+        let body = if is_enum {
+            let mut alternatives = vec![input.parse()?];
+            while input.parse::<Token![|]>().is_ok() {
+                alternatives.push(input.parse()?);
+            }
+            Body::Enum(alternatives)
+        } else {
+            Body::Struct(Fields(bindings(input)?))
+        };
+        Ok(Rule { attrs, name, body })
+    }
+}
+
+/// `Value` is parsed by `value()`
+fn rule_fn(name: &Ident) -> Ident {
+    let mut ret = String::new();
+    for (i, c) in name.to_string().chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            ret.push('_');
+        }
+        ret.extend(c.to_lowercase());
+    }
+    format_ident!("{}", ret, span = name.span())
+}
+
+impl Rule {
+    fn expand(self) -> syn::Result<TokenStream> {
+        let Rule { attrs, name, body } = self;
+        let f = rule_fn(&name);
+        let (ty, parser) = match body {
+            Body::Struct(fields) => {
+                let decl = fields.decl(true);
+                let semi = if decl.is_empty() { quote!(;) } else { quote!() };
+                (quote!(pub struct #name #decl #semi), fields.parser(quote!(#name), name.span())?.into_token_stream())
+            },
+            Body::Enum(alternatives) => {
+                let mut variants = Vec::new();
+                let mut parsers = Vec::new();
+                for alt in alternatives {
+                    match alt {
+                        Alternative::Rule(r) => {
+                            let rf = rule_fn(&r);
+                            variants.push(quote!(#r(#r)));
+                            parsers.push(Expr2::Term(Expr1::Map(Term::Func(rf), parse_quote!(#name::#r))));
+                        },
+                        Alternative::Variant(v, fields) => {
+                            let decl = fields.decl(false);
+                            variants.push(quote!(#v #decl));
+                            parsers.push(fields.parser(quote!(#name::#v), v.span())?);
+                        },
+                    }
+                }
+                let last = Expr::Term(parsers.pop().expect("an enum has alternatives"));
+                let parser = parsers.into_iter().rev().fold(last, |acc, a| Expr::Or(a, Box::new(acc)));
+                (quote!(pub enum #name { #(#variants),* }), parser.into_token_stream())
+            },
+        };
+        let rule = name.to_string();
+        Ok(quote!(
+            #(#attrs)*
+            #ty
+
+            pub fn #f<'a>() -> Parser!(#name) {
+                (#parser).label(#rule).context(#rule).trace(#rule)
+            }
+        ))
+    }
+}
+
+/// rules of `grammar!`
+pub struct Grammar(Vec<Rule>);
+
+impl Parse for Grammar {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut rules = Vec::new();
+        while !input.is_empty() {
+            rules.push(input.parse()?);
+        }
+        Ok(Grammar(rules))
+    }
+}
+
+impl Grammar {
+    pub fn expand(self) -> TokenStream {
+        self.0.into_iter()
+            .map(|rule| rule.expand().unwrap_or_else(syn::Error::into_compile_error))
+            .collect()
+    }
+}
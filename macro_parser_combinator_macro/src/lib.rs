@@ -4,11 +4,18 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::{quote, ToTokens};
-use syn::{Ident, parse::Parse, parse_macro_input, Token, token::Eq, Type};
+use std::str::FromStr;
+use syn::{Ident, parse::{discouraged::Speculative, Parse}, parse_macro_input, Token, token::Eq, Type, Attribute, LitStr, Generics, parenthesized};
 
 mod term;
 use term::Term;
 mod expr;
+mod resolve;
+mod grammar;
+mod derive;
+mod check;
+mod cst;
+mod file;
 
 
 
@@ -20,31 +27,102 @@ pub fn term(input: TokenStream) -> TokenStream {
 }
 
 struct Parser {
+    entry: bool,
+    /// `pub`, used outside the grammar, the other rules are `pub(crate)`
+    export: bool,
+    state: Option<Type>,
     name: Ident,
+    generics: Generics,
+    params: Vec<(Ident, Type)>,
     out_type: Type,
+    label: Option<LitStr>,
     expr: expr::Expr,
+    /// the tokens from the start of the rule to the end, to find it in a grammar file
+    rest: usize,
 }
 
 impl Parse for Parser {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let rest = file::rest(input);
+        let mut entry = false;
+        let mut state = None;
+        // on a fork, so that an error stops before the attributes
+        let fork = input.fork();
+        for attr in fork.call(Attribute::parse_outer)? {
+            if attr.path().is_ident("entry") {
+                entry = true;
+            } else if attr.path().is_ident("state") {
+                state = Some(attr.parse_args()?);
+            } else {
+                return Err(syn::Error::new_spanned(attr, "unknown rule attribute"));
+            }
+        }
+        input.advance_to(&fork);
+        let export = input.parse::<Option<Token![pub]>>()?.is_some();
         let name: Ident = input.parse()?;
+        let generics: Generics = input.parse()?;
+        let params = if input.peek(syn::token::Paren) {
+            let content;
+            let _ = parenthesized!(content in input);
+            let params = file::scope(&content, file::rest(input) + 1, |content| content.parse_terminated(|p| {
+                let name: Ident = p.parse()?;
+                p.parse::<Token![:]>()?;
+                let ty: Type = p.parse()?;
+                Ok((name, ty))
+            }, Token![,]))?;
+            params.into_iter().collect()
+        } else {
+            Vec::new()
+        };
         input.parse::<Token![:]>()?;
         let out_type: Type = input.parse()?;
         //input.parse::<Token![::]>()?;
+        let label = if let Ok(_) = input.parse::<Token![@]>() {
+            Some(input.parse()?)
+        } else {
+            None
+        };
         input.parse::<Eq>()?;
         let expr: expr::Expr = input.parse()?;
         Ok(Self {
+            entry,
+            export,
+            state,
             name,
+            generics,
+            params,
             out_type,
+            label,
             expr,
+            rest,
         })
     }
 }
 
 impl ToTokens for Parser {
     fn to_tokens(&self, tokens: &mut quote::__private::TokenStream) {
-        let Parser { name, out_type, expr } = self;
-        tokens.extend(quote!(pub fn #name<'a>() -> Parser!(#out_type) {
+        let Parser { entry, export, state, name, generics, params, out_type, label, expr, .. } = self;
+        let label = match label {
+            Some(l) => l.value(),
+            None => name.to_string(),
+        };
+        let rule = name.to_string();
+        let expr = if *entry {
+            quote!(((#expr) << eof!()))
+        } else {
+            quote!((#expr))
+        };
+        let (state, expr) = match state {
+            Some(s) => (Some(quote!(state: &'a std::cell::RefCell<#s>)), quote!(#expr.rollback(state))),
+            None => (None, expr),
+        };
+        let expr = quote!(#expr.label(#label).context(#rule).trace(#rule));
+        let args = state.into_iter()
+            .chain(params.iter().map(|(p, t)| quote!(#p: Parser!(#t))));
+        let generic_params = &generics.params;
+        let where_clause = &generics.where_clause;
+        let vis = if *export { quote!(pub) } else { quote!(pub(crate)) };
+        tokens.extend(quote!(#vis fn #name<'a, #generic_params>(#(#args),*) -> Parser!(#out_type) #where_clause {
             #expr
         }))
     }
@@ -67,6 +145,39 @@ impl Parse for MultiParser {
     }
 }
 
+impl MultiParser {
+    fn state_rules(&self) -> Vec<Ident> {
+        let (first, rest) = match self {
+            MultiParser::Multi(a, b) => (a, b.state_rules()),
+            MultiParser::Single(a) => (a, Vec::new()),
+        };
+        first.state.iter().map(|_| first.name.clone()).chain(rest).collect()
+    }
+    fn rules(&self) -> Vec<&Parser> {
+        match self {
+            MultiParser::Multi(a, b) => std::iter::once(a).chain(b.rules()).collect(),
+            MultiParser::Single(a) => vec![a],
+        }
+    }
+    /// resolve the parameters and state of every rule
+    fn resolve(self, state_rules: &[Ident]) -> Self {
+        let f = |mut p: Parser| {
+            let params: Vec<Ident> = p.params.iter().map(|(name, _)| name.clone()).collect();
+            let scope = resolve::Scope {
+                state_rules,
+                params: &params,
+                state: p.state.is_some(),
+            };
+            p.expr = p.expr.resolve(&scope);
+            p
+        };
+        match self {
+            MultiParser::Multi(a, b) => MultiParser::Multi(f(a), Box::new(b.resolve(state_rules))),
+            MultiParser::Single(a) => MultiParser::Single(f(a)),
+        }
+    }
+}
+
 impl ToTokens for MultiParser {
     fn to_tokens(&self, tokens: &mut quote::__private::TokenStream) {
         tokens.extend(match self {
@@ -77,15 +188,101 @@ impl ToTokens for MultiParser {
     }
 }
 
+/// the rules of `parser!`, `#![cst]` before them makes every rule return a `Cst`
+struct Rules {
+    cst: bool,
+    parser: MultiParser,
+}
+
+impl Parse for Rules {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut cst = false;
+        for attr in input.call(Attribute::parse_inner)? {
+            if attr.path().is_ident("cst") {
+                cst = true;
+            } else {
+                return Err(syn::Error::new_spanned(attr, "unknown attribute"));
+            }
+        }
+        let parser = input.parse()?;
+        Ok(Rules { cst, parser })
+    }
+}
+
+impl Rules {
+    /// the rules and the checks, the errors of the checks point into `source` when the rules are from a file
+    fn expand(self, source: Option<&file::Source>) -> quote::__private::TokenStream {
+        let checks = check::check(&self.parser.rules(), source);
+        if self.cst {
+            let rules = self.parser.rules().into_iter()
+                .map(|p| cst::rule(p).unwrap_or_else(syn::Error::into_compile_error));
+            return quote!(#checks #(#rules)*);
+        }
+        let rules = self.parser.state_rules();
+        let parser = self.parser.resolve(&rules);
+        quote!(#checks #parser)
+    }
+}
+
 #[proc_macro]
 pub fn parser(input: TokenStream) -> TokenStream {
-    let parser = parse_macro_input!(input as MultiParser);
-    let ret = quote!(#parser);
-    ret.into()
+    let rules = parse_macro_input!(input as Rules);
+    rules.expand(None).into()
+}
+
+/// rules from a grammar file, the path is relative to the crate root
+#[proc_macro]
+pub fn parser_file(input: TokenStream) -> TokenStream {
+    let path = parse_macro_input!(input as LitStr);
+    let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let full = std::path::Path::new(&root).join(path.value());
+    let source = match std::fs::read_to_string(&full) {
+        Ok(s) => s,
+        Err(e) => {
+            let msg = format!("can not read {}: {}", full.display(), e);
+            return syn::Error::new(path.span(), msg).into_compile_error().into();
+        },
+    };
+    let source = match file::Source::new(path.value(), source) {
+        Ok(s) => s,
+        Err(msg) => return syn::Error::new(path.span(), msg).into_compile_error().into(),
+    };
+    let tokens = match proc_macro2::TokenStream::from_str(&source.text) {
+        Ok(t) => t,
+        Err(e) => {
+            let msg = format!("{}: {}", source.path, e);
+            return syn::Error::new(path.span(), msg).into_compile_error().into();
+        },
+    };
+    match source.parse::<Rules>(tokens) {
+        Ok(rules) => {
+            let rules = rules.expand(Some(&source));
+            let full = full.display().to_string();
+            // rebuild when the file changes
+            quote!(
+                const _: &str = include_str!(#full);
+                #rules
+            ).into()
+        },
+        Err(msg) => syn::Error::new(path.span(), msg).into_compile_error().into(),
+    }
+}
+
+#[proc_macro]
+pub fn grammar(input: TokenStream) -> TokenStream {
+    let grammar = parse_macro_input!(input as grammar::Grammar);
+    grammar.expand().into()
+}
+
+#[proc_macro_derive(Parse, attributes(token, regex, sep, suffix))]
+pub fn derive_parse(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as syn::DeriveInput);
+    derive::expand(&input).into()
 }
 
 #[test]
 fn test() {
+    use macro_parser_combinator_core::*;
 
     let input = "abc";
     let parser = token!("ab") * token!("c");
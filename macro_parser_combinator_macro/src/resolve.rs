@@ -0,0 +1,108 @@
+use syn::Ident;
+
+use crate::expr::{Binding, Expr, Expr1, Expr2};
+use crate::term::Term;
+
+/// what the names in a rule refer to
+pub struct Scope<'s> {
+    /// rules that take the state
+    pub state_rules: &'s [Ident],
+    /// parameters of the rule
+    pub params: &'s [Ident],
+    /// the rule itself has `#[state]`
+    pub state: bool,
+}
+
+impl Expr {
+    /// turn parameter names into the parameters, pass the state to the rules that
+    /// take it, and undo state changes wherever a failure is backtracked:
+    /// `|`, `[]`, `{}` and `~`
+    pub fn resolve(self, scope: &Scope) -> Self {
+        match self {
+            Expr::Or(a, b) => Expr::Or(rollback(a.resolve(scope), scope), Box::new(b.resolve(scope))),
+            Expr::Term(a) => Expr::Term(a.resolve(scope)),
+        }
+    }
+}
+
+fn rollback(e: Expr2, scope: &Scope) -> Expr2 {
+    if scope.state {
+        Expr2::Term(Expr1::Term(Term::Rollback(Box::new(Expr::Term(e)))))
+    } else {
+        e
+    }
+}
+
+impl Expr2 {
+    fn resolve(self, scope: &Scope) -> Self {
+        match self {
+            Expr2::Chain(a, rest) => {
+                let rest = rest.into_iter().map(|(op, e)| (op, e.resolve(scope))).collect();
+                Expr2::Chain(a.resolve(scope), rest)
+            },
+            Expr2::Action(bindings, block) => {
+                let bindings = bindings.into_iter()
+                    .map(|b| Binding { name: b.name, expr: b.expr.resolve(scope) })
+                    .collect();
+                Expr2::Action(bindings, block)
+            },
+            Expr2::Term(a) => Expr2::Term(a.resolve(scope)),
+        }
+    }
+}
+
+impl Expr1 {
+    fn resolve(self, scope: &Scope) -> Self {
+        match self {
+            Expr1::Map(t, e) => Expr1::Map(t.resolve(scope), e),
+            Expr1::Flatmap(t, e) => Expr1::Flatmap(t.resolve(scope), e),
+            Expr1::Recover(t, s) => Expr1::Recover(term_rollback(t.resolve(scope), scope), s),
+            Expr1::Label(t, s) => Expr1::Label(t.resolve(scope), s),
+            Expr1::Term(t) => Expr1::Term(t.resolve(scope)),
+        }
+    }
+}
+
+fn term_expr(t: Term) -> Expr {
+    Expr::Term(Expr2::Term(Expr1::Term(t)))
+}
+
+fn term_rollback(t: Term, scope: &Scope) -> Term {
+    if scope.state {
+        Term::Rollback(Box::new(term_expr(t)))
+    } else {
+        t
+    }
+}
+
+impl Term {
+    fn resolve(self, scope: &Scope) -> Self {
+        let inner = |e: Box<Expr>| {
+            let e = Box::new(e.resolve(scope));
+            if scope.state {
+                Box::new(term_expr(Term::Rollback(e)))
+            } else {
+                e
+            }
+        };
+        let stateful = |f: &Ident| scope.state && scope.state_rules.contains(f);
+        match self {
+            Term::Func(f) if scope.params.contains(&f) => Term::Param(f),
+            Term::Func(f) if stateful(&f) => Term::StateFunc(f),
+            Term::Paren(e) => Term::Paren(Box::new(e.resolve(scope))),
+            Term::Try(e) => Term::Try(inner(e)),
+            Term::Many(e) => Term::Many(inner(e)),
+            Term::ManySep(e, s) => Term::ManySep(inner(e), s),
+            Term::Call(f, args) => {
+                let args = args.into_iter().map(|e| e.resolve(scope)).collect();
+                if stateful(&f) {
+                    Term::StateCall(f, args)
+                } else {
+                    Term::Call(f, args)
+                }
+            },
+            Term::Rollback(e) => Term::Rollback(Box::new(e.resolve(scope))),
+            t => t,
+        }
+    }
+}
@@ -1,8 +1,11 @@
 
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
 use quote::{quote, ToTokens};
-use syn::{Ident, parse::Parse, LitStr, parenthesized, bracketed, braced};
+use syn::{Ident, Type, parse::{Parse, Parser as _}, punctuated::Punctuated, LitStr, Token, parenthesized, bracketed, braced};
+use syn::token::{Brace, Bracket, Paren};
 
 use crate::expr::Expr;
+use crate::file;
 
 /// term
 /// 1.function: whitespace
@@ -12,6 +15,16 @@ use crate::expr::Expr;
 /// 5.try: [a]
 /// 6.many: {a}
 /// 7.many with sep: {a(",")}
+/// 8.call: indented_block(header, item), the arguments are parsers: f("x")
+///
+/// only made when resolving names in a rule:
+/// 1.parameter of the rule
+/// 2.function that takes the state
+/// 3.call that takes the state
+/// 4.undo state changes on failure
+///
+/// only made by `#[derive(Parse)]`:
+/// 1.parser of a type: `Type::parser()`
 pub enum Term {
     Func(Ident),
     Regex(LitStr),
@@ -20,20 +33,31 @@ pub enum Term {
     Try(Box<Expr>),
     Many(Box<Expr>),
     ManySep(Box<Expr>, LitStr),
+    Call(Ident, Vec<Expr>),
+    Param(Ident),
+    StateFunc(Ident),
+    StateCall(Ident, Vec<Expr>),
+    Rollback(Box<Expr>),
+    Parser(Box<Type>),
 }
 
 impl Parse for Term {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        if let Ok(t) = input.parse::<TermParen>() {
+        // the brackets decide the term, so that an error inside them is the error of the term
+        if input.peek(Paren) {
+            let t: TermParen = input.parse()?;
             Ok(Term::Paren(Box::new(t.expr)))
-        } else if let Ok(t) = input.parse::<TermTry>() {
+        } else if input.peek(Bracket) {
+            let t: TermTry = input.parse()?;
             Ok(Term::Try(Box::new(t.expr)))
-        } else if let Ok(t) = input.parse::<TermMany>() {
-            match t {
+        } else if input.peek(Brace) {
+            match input.parse::<TermMany>()? {
                 TermMany::Many(e) => Ok(Term::Many(Box::new(e))),
                 TermMany::ManySep(e, s) => Ok(Term::ManySep(Box::new(e), s)),
             }
-            
+        } else if input.peek(Ident) && input.peek2(Paren) {
+            let t: TermCall = input.parse()?;
+            Ok(Term::Call(t.func, t.args))
         } else {
             input.clone().step(|cursor| {
                 if let Some((lit, rest)) = cursor.literal() {
@@ -85,6 +109,24 @@ impl ToTokens for Term {
             },
             Term::ManySep(expr, sep) => {
                 quote!((#expr).many_sep(sep!(#sep)))
+            },
+            Term::Call(f, args) => {
+                quote!(#f(#(#args),*))
+            },
+            Term::Param(p) => {
+                quote!(#p)
+            },
+            Term::StateFunc(f) => {
+                quote!(tobox!(#f(state)))
+            },
+            Term::StateCall(f, args) => {
+                quote!(#f(state, #(#args),*))
+            },
+            Term::Rollback(expr) => {
+                quote!((#expr).rollback(state))
+            },
+            Term::Parser(ty) => {
+                quote!(tobox!(<#ty>::parser()))
             }
         });
     }
@@ -98,7 +140,7 @@ impl Parse for TermParen {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let content;
         let _ = parenthesized!(content in input);
-        let expr: Expr = content.parse()?;
+        let expr = file::scope(&content, file::rest(input) + 1, Expr::parse)?;
         Ok(TermParen { expr })
     }
 }
@@ -111,20 +153,35 @@ impl Parse for TermTry {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let content;
         let _ = bracketed!(content in input);
-        let expr: Expr = content.parse()?;
+        let expr = file::scope(&content, file::rest(input) + 1, Expr::parse)?;
         Ok(TermTry { expr })
     }
 }
 
-struct Sep {
-    sep: LitStr,
+struct TermCall {
+    func: Ident,
+    args: Vec<Expr>,
+}
+
+impl Parse for TermCall {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let func: Ident = input.parse()?;
+        let content;
+        let _ = parenthesized!(content in input);
+        let args = file::scope(&content, file::rest(input) + 1, Punctuated::<Expr, Token![,]>::parse_terminated)?;
+        Ok(TermCall { func, args: args.into_iter().collect() })
+    }
+}
+
+pub(crate) struct Sep {
+    pub(crate) sep: LitStr,
 }
 
 impl Parse for Sep {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let content;
         let _ = parenthesized!(content in input);
-        let sep = content.parse()?;
+        let sep = file::scope(&content, file::rest(input) + 1, <LitStr as Parse>::parse)?;
         Ok(Sep { sep })
     }
 }
@@ -138,11 +195,25 @@ impl Parse for TermMany {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let content;
         let _ = braced!(content in input);
-        let expr: Expr = content.parse()?;
-        if let Ok(sep) = content.parse::<Sep>() {
-            Ok(TermMany::ManySep(expr, sep.sep))
-        }else {
-            Ok(TermMany::Many(expr))
+        // a last `("sep")` is the separator, not the arguments of a call or a sync point:
+        // `{a(",")}` is `a` with sep, `{(f("x"))}` is many calls, `{a ~ (";")}` is recovery
+        let mut tokens: Vec<TokenTree> = content.parse::<TokenStream>()?.into_iter().collect();
+        let sep = match tokens.as_slice() {
+            [.., TokenTree::Punct(p), _] if p.as_char() == '~' => None,
+            [_, .., TokenTree::Group(g)] if g.delimiter() == Delimiter::Parenthesis => {
+                syn::parse2::<LitStr>(g.stream()).ok()
+            },
+            _ => None,
+        };
+        // the separator is a group of one literal
+        let after = file::rest(input) + 1 + if sep.is_some() { 3 } else { 0 };
+        if sep.is_some() {
+            tokens.pop();
+        }
+        let expr = (|content: syn::parse::ParseStream| file::scope(content, after, Expr::parse)).parse2(tokens.into_iter().collect())?;
+        match sep {
+            Some(sep) => Ok(TermMany::ManySep(expr, sep)),
+            None => Ok(TermMany::Many(expr)),
         }
     }
 }
@@ -7,6 +7,7 @@ pub use regex::Regex;
 mod tests {
     use std::collections::BTreeMap;
     use macro_parser_combinator_core::*;
+    use macro_parser_combinator_macro::{parser, grammar, parser_file, Parse};
 
     #[derive(Debug)]
     enum Json {
@@ -78,4 +79,395 @@ mod tests {
         }
 
     }
+
+    type Stmts = Vec<Result<i64, (String, Location)>>;
+
+    parser!{
+        stmt: i64 = whitespace >> int << ";"
+        stmts: Stmts = {stmt ~ (";")}
+    }
+
+    #[test]
+    fn test_recover() {
+        let ret = stmts().run("1; 2; x + 3; 4;").unwrap();
+        assert_eq!(ret.len(), 4);
+        assert_eq!(ret[1].as_ref().ok(), Some(&2));
+        assert_eq!(ret[2].as_ref().unwrap_err().1.col, 7);
+        assert_eq!(ret[3].as_ref().ok(), Some(&4));
+
+        let ret = stmts().run("1; 2; x").unwrap();
+        assert_eq!(ret.len(), 3);
+        assert_eq!(ret[2].as_ref().unwrap_err().1.col, 7);
+    }
+
+    parser!{
+        #[entry]
+        numbers: Stmts = stmts
+        #[entry]
+        plain: Ints = {stmt}
+    }
+
+    #[test]
+    fn test_eof() {
+        assert!(numbers().run("1; 2;").is_ok());
+        assert_eq!(numbers().run("1; 2; }").unwrap()[2].as_ref().unwrap_err().1.col, 7);
+        assert_eq!(plain().run("1; 2; }").unwrap_err().1.col, 7);
+        assert_eq!(stmt().many().run_complete("1; 2; }").unwrap_err().1.col, 7);
+    }
+
+    type Pairs = Vec<(String, i64)>;
+
+    parser!{
+        pair: (String, i64) @ "key value pair" = (escaped_quoted << whitespace << ":") * (whitespace >> int)
+        pairs: Pairs = "{" >> {pair(",")} << ("}" @ "closing brace")
+        one: (String, i64) = "{" >> pair << "}"
+    }
+
+    #[cfg(not(feature = "context"))]
+    #[test]
+    fn test_label() {
+        assert_eq!(pair().run("x").unwrap_err().0, "should be key value pair but get x");
+        assert_eq!(pairs().run("x").unwrap_err().0, "should be pairs but get x");
+        assert_eq!(pairs().run(r#"{"a": 1x"#).unwrap_err().0, "should be closing brace but get x");
+    }
+
+    #[cfg(feature = "context")]
+    #[test]
+    fn test_label_context() {
+        assert_eq!(pair().run("x").unwrap_err().0, "should be key value pair but get x (in pair)");
+        assert_eq!(pairs().run("x").unwrap_err().0, "should be pairs but get x (in pairs)");
+        assert_eq!(pairs().run(r#"{"a": 1x"#).unwrap_err().0, "should be closing brace but get x (in pairs)");
+    }
+
+    type Names = Vec<String>;
+
+    parser!{
+        #[state(Names)]
+        decl: String = ("let" >> r"[a-z]+" << ";") -> (move |x: String| { state.borrow_mut().push(x.clone()); x })
+        #[state(Names)]
+        var: String = (r"[a-z]+" << ";") => (move |x: String| if state.borrow().contains(&x) {
+            Ok(x)
+        } else {
+            Err((format!("undefined {}", x), Location::new()))
+        })
+        #[state(Names)]
+        line: String = (decl << "!") | decl | var
+        #[entry]
+        #[state(Names)]
+        block: Names = {whitespace >> line}
+    }
+
+    #[test]
+    fn test_state() {
+        let names = std::cell::RefCell::new(Vec::new());
+        assert_eq!(block(&names).run("let a; a; let b; a;").unwrap().len(), 4);
+        assert_eq!(*names.borrow(), vec!["a", "b"]);
+        let names = std::cell::RefCell::new(Vec::new());
+        assert!(block(&names).run("let a; b;").is_err());
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Node(String, Vec<Node>);
+    type Nodes = Vec<Node>;
+
+    parser!{
+        name: String = r"[a-z]+" << whitespace
+        node: Node = indented_block(name << ":", node) -> (|(n, c)| Node(n, c))
+            | name -> (|n| Node(n, vec![]))
+        nodes: Nodes = aligned(node)
+    }
+
+    #[test]
+    fn test_indent() {
+        let leaf = |n: &str| Node(n.to_string(), vec![]);
+        let input = "a:\n  b\n  c:\n    d\n  e\nf\n";
+        assert_eq!(nodes().run(input).unwrap(), vec![
+            Node("a".to_string(), vec![leaf("b"), Node("c".to_string(), vec![leaf("d")]), leaf("e")]),
+            leaf("f"),
+        ]);
+        assert!(node().run("a:\nb").is_ok_and(|n| n == leaf("a")));
+        assert!(same_line(name(), name()).run("a b").is_ok());
+        assert!(same_line(name(), name()).run("a\nb").is_err());
+    }
+
+    type Ints = Vec<i64>;
+
+    parser!{
+        list<T>(p: T): Vec<T> = "[" >> {p(",")} << "]"
+        tagged<T>(tag: String, p: T): (String, T) = (whitespace >> tag << ":") * p
+        ints: Ints = list(whitespace >> int)
+        matrix: Vec<Ints> = list(whitespace >> ints)
+        tagged_pairs: (String, Pairs) = tagged(r"[a-z]+", list(whitespace >> pair))
+        xs: Vec<&'a str> = list("x")
+        xs_lists: Vec<Vec<&'a str>> = {(list("x"))(";")}
+    }
+
+    #[test]
+    fn test_params() {
+        assert_eq!(ints().run("[1, 2, 3]").unwrap(), vec![1, 2, 3]);
+        assert_eq!(matrix().run("[[1], [2, 3]]").unwrap(), vec![vec![1], vec![2, 3]]);
+        assert_eq!(list(int!()).run("[4,5]").unwrap(), vec![4, 5]);
+        let (tag, pairs) = tagged_pairs().run(r#"pairs: ["a": 1, "b": 2]"#).unwrap();
+        assert_eq!(tag, "pairs");
+        assert_eq!(pairs, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+        assert_eq!(xs().run("[x,x]").unwrap(), vec!["x", "x"]);
+        assert_eq!(xs_lists().run("[x];[]").unwrap(), vec![vec!["x"], vec![]]);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Field {
+        name: String,
+        ty: String,
+        default: Option<i64>,
+    }
+    type Fields = Vec<Field>;
+
+    parser!{
+        ident: String = r"[a-z]+" << whitespace
+        field: Field = name:ident ":" ty:ident default:["=" >> int] -> { Field { name, ty, default } }
+            | "_" name:ident -> { Field { name, ty: "unit".to_string(), default: None } }
+        fields: Fields = {(whitespace >> field)(",")}
+    }
+
+    #[test]
+    fn test_action() {
+        let field = |name: &str, ty: &str, default| Field { name: name.to_string(), ty: ty.to_string(), default };
+        assert_eq!(fields().run("a: int = 1, b: str, _c").unwrap(), vec![
+            field("a", "int", Some(1)),
+            field("b", "str", None),
+            field("c", "unit", None),
+        ]);
+    }
+
+    parser!{
+        triple: (i64, String, i64) = "(" >> int * ("," >> ident) * ("," >> int) << ")"
+        quad: (String, String, String, String) = ident * ident * ident * ident << ";"
+    }
+
+    #[test]
+    fn test_chain() {
+        assert_eq!(triple().run("(1, a, 2)").unwrap(), (1, "a".to_string(), 2));
+        let (a, _, _, d) = quad().run("a b c d;").unwrap();
+        assert_eq!((a.as_str(), d.as_str()), ("a", "d"));
+    }
+
+    grammar!{
+        #[derive(Debug, PartialEq)]
+        Doc = "{" members:{Member(",")} "}"
+        #[derive(Debug, PartialEq)]
+        Member = key:escaped_quoted ":" value:Elem
+        #[derive(Debug, PartialEq)]
+        Elem = Doc | Null("null") | Number(n:float) | Text(s:escaped_quoted) | List("[" items:{Elem(",")} "]")
+        #[derive(Debug, PartialEq)]
+        Binding = "let" name:r"[a-z]+" ty:[":" r"[a-z]+"] "=" value:Elem ";"
+    }
+
+    #[test]
+    fn test_grammar() {
+        let ret = doc().run(r#"{"a": 1.5, "b": null, "c": ["x", {}]}"#).unwrap();
+        assert_eq!(ret.members.len(), 3);
+        assert_eq!(ret.members[0].key, "a");
+        assert_eq!(*ret.members[0].value, Elem::Number { n: 1.5 });
+        assert_eq!(*ret.members[1].value, Elem::Null);
+        assert_eq!(*ret.members[2].value, Elem::List { items: vec![
+            Elem::Text { s: "x".to_string() },
+            Elem::Doc(Doc { members: vec![] }),
+        ] });
+        let ret = binding().run("let x: int = 1;").unwrap();
+        assert_eq!((ret.name.as_str(), ret.ty.as_deref()), ("x", Some("int")));
+        assert_eq!(binding().run("let y = null;").unwrap().ty, None);
+    }
+
+    #[derive(Debug, PartialEq, Parse)]
+    enum Token {
+        #[token("null")]
+        Null,
+        Int(i64),
+        Str(String),
+        #[token("[")]
+        #[suffix("]")]
+        List(#[sep(",")] Vec<Token>),
+    }
+
+    #[derive(Debug, PartialEq, Parse)]
+    #[token("let")]
+    #[suffix(";")]
+    struct Let {
+        #[regex(r"[a-z]+")]
+        name: String,
+        #[token("=")]
+        value: Box<Token>,
+    }
+
+    #[test]
+    fn test_derive() {
+        assert_eq!(Token::parser().run(r#"[1, null, ["a"]]"#).unwrap(), Token::List(vec![
+            Token::Int(1),
+            Token::Null,
+            Token::List(vec![Token::Str("a".to_string())]),
+        ]));
+        let ret = Let::parser().run("let x = [];").unwrap();
+        assert_eq!((ret.name.as_str(), *ret.value), ("x", Token::List(vec![])));
+        assert!(Let::parser().run("let x = ;").is_err());
+    }
+
+    parser_file!("src/list.peg");
+
+    #[test]
+    fn test_parser_file() {
+        assert_eq!(items().run("[1, 2, 3]").unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |n: usize| "[".repeat(n) + &"]".repeat(n);
+        assert!(value().run(&nested(100)).is_ok());
+        let e = value().run(&nested(10_000)).unwrap_err();
+        assert!(e.0.contains("nested deeper than the recursion limit 128"));
+        assert_eq!(e.1.col, 130);
+        assert!(elem().run(&nested(10_000)).is_err());
+        // every run starts again, `run_with_out` does not leave the limit hit for the next one
+        assert!(value().run_with_out(&nested(10_000), Location::new()).0.is_err());
+        assert!(value().run(&nested(2)).is_ok());
+    }
+
+    parser!{
+        #![cst]
+        #[entry]
+        call: _ = callee * "(" * {arg(",")} * ")"
+        callee: _ = r"[a-z]+" << whitespace
+        arg: _ = whitespace >> (int | callee) -> (|x| x)
+    }
+
+    fn leaves(cst: &Cst) -> Vec<String> {
+        match cst {
+            Cst::Node { children, .. } => children.iter().flat_map(leaves).collect(),
+            Cst::Token { text, .. } => vec![text.clone()],
+            Cst::Trivia { .. } => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_cst() {
+        let tree = call().run("f(1, x)").unwrap();
+        assert_eq!(leaves(&tree), ["f", "(", "1", ",", "x", ")"]);
+        assert_eq!(tree.span(), Span { start: Location { line: 1, col: 1 }, end: Location { line: 1, col: 8 } });
+        let Cst::Node { rule, children, .. } = tree else {
+            panic!("call is a node");
+        };
+        assert_eq!(rule, "call");
+        let rules: Vec<_> = children.iter().filter_map(|c| match c {
+            Cst::Node { rule, .. } => Some(*rule),
+            _ => None,
+        }).collect();
+        assert_eq!(rules, ["callee", "arg", "arg"]);
+        assert_eq!(children[4].span().start, Location { line: 1, col: 6 });
+        assert!(call().run("f(1,").is_err());
+    }
+
+    #[test]
+    fn test_lossless() {
+        let input = "f (1,\n  x )  ";
+        let tree = call().run(input).unwrap();
+        assert_eq!(tree.to_string(), input);
+        assert_eq!(leaves(&tree), ["f", "(", "1", ",", "x", ")"]);
+        let Cst::Node { children, .. } = &tree else {
+            panic!("call is a node");
+        };
+        let Cst::Node { children, .. } = &children[4] else {
+            panic!("x is an arg");
+        };
+        let Cst::Node { children, .. } = &children[0] else {
+            panic!("x is a callee");
+        };
+        let Cst::Token { text, leading, trailing, span } = &children[0] else {
+            panic!("x is a token");
+        };
+        assert_eq!((leading.as_str(), text.as_str(), trailing.as_str()), ("\n  ", "x", " "));
+        assert_eq!(span.start, Location { line: 2, col: 3 });
+    }
+
+    #[cfg(feature = "incremental")]
+    #[test]
+    fn test_incremental() {
+        let mut text = "f(1, x)".to_string();
+        let mut memo = Memo::new();
+        call().run_incremental(&text, &mut memo).unwrap();
+        memo.edit(&mut text, 5..6, "yz");
+        let tree = call().run_incremental(&text, &mut memo).unwrap();
+        assert_eq!(tree, call().run(&text).unwrap());
+        assert_eq!(leaves(&tree), ["f", "(", "1", ",", "yz", ")"]);
+        assert_eq!(memo.reused(), 2);
+
+        memo.edit(&mut text, 0..1, "gh\n ");
+        let tree = call().run_incremental(&text, &mut memo).unwrap();
+        assert_eq!(tree, call().run(&text).unwrap());
+        assert_eq!(tree.to_string(), "gh\n (1, yz)");
+        assert_eq!(memo.reused(), 2);
+    }
+
+    #[cfg(feature = "incremental")]
+    #[test]
+    fn test_incremental_prune() {
+        // without `(` the arguments are not parsed, their nodes are not kept
+        let mut text = "f(1, x)".to_string();
+        let mut memo = Memo::new();
+        call().run_incremental(&text, &mut memo).unwrap();
+        memo.edit(&mut text, 1..2, "");
+        assert!(call().run_incremental(&text, &mut memo).is_err());
+        let mut fresh = Memo::new();
+        assert!(call().run_incremental(&text, &mut fresh).is_err());
+        assert_eq!(memo.len(), fresh.len());
+        memo.edit(&mut text, 1..1, "(");
+        let tree = call().run_incremental(&text, &mut memo).unwrap();
+        assert_eq!(tree, call().run(&text).unwrap());
+    }
+
+    #[cfg(feature = "incremental")]
+    mod other {
+        use super::*;
+
+        parser!{
+            #![cst]
+            call: _ = r"[a-z]+"
+        }
+    }
+
+    #[cfg(feature = "incremental")]
+    #[test]
+    fn test_incremental_grammars() {
+        // `call` of another grammar does not reuse the node of this one
+        let text = "f(1, x)";
+        let mut memo = Memo::new();
+        call().run_incremental(text, &mut memo).unwrap();
+        let tree = other::call().run_incremental(text, &mut memo).unwrap();
+        assert_eq!(tree, other::call().run(text).unwrap());
+    }
+
+    #[cfg(feature = "incremental")]
+    parser!{
+        #![cst]
+        head: _ = "a" * ["x" * "y"]
+    }
+
+    #[cfg(feature = "incremental")]
+    #[test]
+    fn test_incremental_backtrack() {
+        // `["x" * "y"]` read the `z` before it failed, the node ends before it
+        let mut text = "a x z".to_string();
+        let mut memo = Memo::new();
+        head().run_incremental(&text, &mut memo).unwrap();
+        memo.edit(&mut text, 4..5, "y");
+        let tree = head().run_incremental(&text, &mut memo).unwrap();
+        assert_eq!(tree, head().run(&text).unwrap());
+        assert_eq!(leaves(&tree), ["a", "x", "y"]);
+    }
+
+    #[cfg(feature = "context")]
+    #[test]
+    fn test_context() {
+        assert_eq!(one().run(r#"{"a": x}"#).unwrap_err().0, "should be regex [-+]?[0-9]+ (in one > pair)");
+        assert_eq!(one().run("x").unwrap_err().0, "should be one but get x (in one)");
+        assert!(one().run(r#"{"a": 1}"#).is_ok());
+    }
 }